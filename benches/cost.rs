@@ -0,0 +1,37 @@
+//! Compare with and without `--features parallel`:
+//! `cargo bench --bench cost [--features parallel]`. Lines are only split
+//! across threads from 2048 pixels on, hence the upscaled image.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::{imageops::FilterType, io::Reader as ImageReader};
+use seam_carving::{
+    cost::{build_cost_matrix, Direction},
+    sobel::Sobel,
+};
+
+fn cost_matrix(c: &mut Criterion) {
+    let img = ImageReader::open("./test_data/src/broadway_tower.jpg")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let mut group = c.benchmark_group("build_cost_matrix");
+    for scale in [1, 3] {
+        let img = img.resize(
+            img.width() * scale,
+            img.height() * scale,
+            FilterType::Triangle,
+        );
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let gray = img.into_luma8().into_vec();
+        let energy = Sobel::new().apply(&gray, width, height);
+        for (name, dir) in [("row", Direction::Row), ("column", Direction::Column)] {
+            group.bench_function(format!("{name} {width}x{height}"), |b| {
+                b.iter(|| build_cost_matrix(&energy, width, height, dir))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cost_matrix);
+criterion_main!(benches);
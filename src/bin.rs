@@ -1,8 +1,137 @@
-use std::path::PathBuf;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use clap::Parser;
-use image::io::Reader as ImageReader;
-use seam_carving::SeamCarver;
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use image::{io::Reader as ImageReader, DynamicImage, ImageError};
+use seam_carving::{
+    cost::EnergyMode,
+    energy::{ColorGradient, ColorSpace, DualGradient, Entropy, L1Gradient, Saliency},
+    filter::{Gaussian, Normalize},
+    seam::{MapBlend, SeamOrder},
+    sobel::{self, Border, Hog, Sobel},
+    CarveError, EnergyMap, SeamCarver,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Order {
+    /// Remove all the horizontal seams, then all the vertical ones
+    ColumnsThenRows,
+    /// Remove the cheaper of the best horizontal and vertical seams
+    Greedy,
+    /// Pick the interleaving with the lowest total energy (slow)
+    Optimal,
+}
+
+impl From<Order> for SeamOrder {
+    fn from(order: Order) -> Self {
+        match order {
+            Order::ColumnsThenRows => SeamOrder::ColumnsThenRows,
+            Order::Greedy => SeamOrder::Greedy,
+            Order::Optimal => SeamOrder::Optimal,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Kernel {
+    /// 3x3 Sobel
+    #[value(name = "3")]
+    X3,
+    /// 5x5 Sobel
+    #[value(name = "5")]
+    X5,
+    /// 7x7 Sobel
+    #[value(name = "7")]
+    X7,
+    /// 3x3 Scharr
+    Scharr,
+    /// 3x3 Prewitt
+    Prewitt,
+    /// 2x2 Roberts cross
+    Roberts,
+    /// 5x5 Laplacian of Gaussian
+    Log,
+}
+
+impl From<Kernel> for sobel::Kernel {
+    fn from(kernel: Kernel) -> Self {
+        match kernel {
+            Kernel::X3 => sobel::Kernel::X3,
+            Kernel::X5 => sobel::Kernel::X5,
+            Kernel::X7 => sobel::Kernel::X7,
+            Kernel::Scharr => sobel::Kernel::Scharr,
+            Kernel::Prewitt => sobel::Kernel::Prewitt,
+            Kernel::Roberts => sobel::Kernel::Roberts,
+            Kernel::Log => sobel::Kernel::LoG,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EnergyFn {
+    /// Edge detection with the --kernel operator and --border mode
+    Sobel,
+    /// Sum of the absolute central differences
+    L1,
+    /// Magnitude of the central differences
+    Dual,
+    /// Sum of the gradients of the RGB channels
+    Color,
+    /// Gradient of the image in CIELAB
+    Lab,
+    /// Local entropy in a --window sized window, plus the L1 gradient
+    Entropy,
+    /// Local entropy in a --window sized window alone
+    EntropyOnly,
+    /// Gradient of --kernel over the largest bin of the histogram of
+    /// oriented gradients of the --cell around every pixel
+    Hog,
+    /// Frequency-tuned saliency, keeps the main subject whole
+    Saliency,
+    /// Saliency times the energy of --kernel
+    SaliencySobel,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BorderArg {
+    /// Leave the pixels past the border out
+    Truncate,
+    /// Repeat the border pixel
+    Replicate,
+    /// Mirror the image
+    Reflect,
+    /// Continue with the opposite side
+    Wrap,
+    /// Pad with black
+    Zero,
+}
+
+impl From<BorderArg> for Border {
+    fn from(border: BorderArg) -> Self {
+        match border {
+            BorderArg::Truncate => Border::Truncate,
+            BorderArg::Replicate => Border::Replicate,
+            BorderArg::Reflect => Border::Reflect,
+            BorderArg::Wrap => Border::Wrap,
+            BorderArg::Zero => Border::Zero,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NormalizeArg {
+    /// Use the energy as is
+    None,
+    /// Rescale the energy to 0..1
+    MinMax,
+    /// Clip the energy to its --percentile quantile
+    Percentile,
+    /// Take the log of the energy
+    Log,
+}
 
 /// Seam carving
 #[derive(Parser, Debug)]
@@ -23,27 +152,433 @@ struct Args {
     #[arg(short='l', long, default_value_t = 0.9)]
     height_ratio: f32,
 
+    /// Use forward energy instead of backward energy. It is computed from
+    /// the grayscale image, so the energy options do not apply, and an
+    /// --energy-map is added to it rather than replacing it.
+    #[arg(short, long, conflicts_with_all = [
+        "kernel", "border", "energy", "window", "cell", "bins", "blur_sigma", "normalize",
+        "percentile",
+    ])]
+    forward: bool,
+
+    /// Mask of the pixels to remove, the result is still resized to the
+    /// requested ratios so `-w 1 -l 1` restores the original size
+    #[arg(long)]
+    remove_mask: Option<String>,
+
+    /// Mask of the pixels to keep untouched, seams only cross it where
+    /// every seam has to
+    #[arg(long)]
+    protect: Option<String>,
+
+    /// The order in which horizontal and vertical seams are removed
+    #[arg(long, value_enum, default_value_t = Order::ColumnsThenRows)]
+    order: Order,
+
+    /// The edge detection operator of the sobel, hog and saliency-sobel
+    /// energies, 3 by default
+    #[arg(short, long, value_enum)]
+    kernel: Option<Kernel>,
+
+    /// What the kernel sees past the image border, truncate by default
+    #[arg(long, value_enum)]
+    border: Option<BorderArg>,
+
+    /// The energy function seams avoid
+    #[arg(short, long, value_enum, default_value_t = EnergyFn::Sobel)]
+    energy: EnergyFn,
+
+    /// The side of the window of the entropy energies, 9 by default
+    #[arg(long)]
+    window: Option<usize>,
+
+    /// The side of the cells of the HoG energy, 11 by default
+    #[arg(long)]
+    cell: Option<usize>,
+
+    /// The number of orientation bins of the HoG energy, 8 by default
+    #[arg(long)]
+    bins: Option<usize>,
+
+    /// Per-pixel importance replacing the energy: an image, black being
+    /// the cheapest, or a 2D npy array. Images and uint8 arrays are scaled
+    /// to 0..1. Added to the energy with --forward.
+    #[arg(long)]
+    energy_map: Option<String>,
+
+    /// Add the energy map times this weight to the energy instead of
+    /// replacing it
+    #[arg(long, requires = "energy_map")]
+    map_weight: Option<f32>,
+
+    /// Blur the image with this standard deviation, positive and at most
+    /// 100, before computing the energy to ignore noise
+    #[arg(long)]
+    blur_sigma: Option<f32>,
+
+    /// The side of the blur kernel, three sigmas each side by default
+    #[arg(long, requires = "blur_sigma")]
+    blur_size: Option<usize>,
+
+    /// How the energy is rescaled, backward energy only
+    #[arg(long, value_enum, default_value_t = NormalizeArg::None)]
+    normalize: NormalizeArg,
+
+    /// The quantile the energy is clipped to by --normalize percentile,
+    /// 0.99 by default
+    #[arg(long)]
+    percentile: Option<f32>,
 }
 
-fn main() {
-    let args = Args::parse();
-    let src_path = &args.path;
-    let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+/// Why the command failed, each with its own exit code
+#[derive(Debug)]
+enum Failure {
+    Read(String, ImageError),
+    /// Not a 2D npy array of a supported type
+    Npy(String),
+    Write(String, ImageError),
+    /// The input path has no file name to derive the output's from
+    Output(String),
+    Carve(CarveError),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        // 1 and 2 are used by the runtime and clap
+        match self {
+            Self::Read(..) | Self::Npy(_) => 3,
+            Self::Write(..) | Self::Output(_) => 4,
+            Self::Carve(CarveError::ZeroSizeTarget) => 5,
+            Self::Carve(CarveError::InvalidDimensions { .. }) => 6,
+            Self::Carve(CarveError::InvalidBufferLength { .. }) => 7,
+            Self::Carve(CarveError::UnsupportedPixelType(_)) => 8,
+            Self::Carve(CarveError::ImageTooSmall { .. }) => 9,
+            Self::Carve(CarveError::EnlargementDenied { .. }) => 10,
+            Self::Carve(CarveError::InvalidSigma(_)) => 11,
+            Self::Carve(CarveError::KernelNotUsed) => 12,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "cannot read {path}: {err}"),
+            Self::Npy(path) => write!(
+                f,
+                "cannot read {path}: expected a 2D float32, float64 or uint8 npy array"
+            ),
+            Self::Write(path, err) => write!(f, "cannot write {path}: {err}"),
+            Self::Output(path) => {
+                write!(f, "cannot name the output after {path}, pass --output")
+            }
+            Self::Carve(CarveError::ZeroSizeTarget) => {
+                write!(
+                    f,
+                    "the width and height ratios must keep at least one pixel"
+                )
+            }
+            Self::Carve(CarveError::InvalidDimensions { expected, found }) => write!(
+                f,
+                "the mask or energy map is {}x{} but the image is {}x{}",
+                found.0, found.1, expected.0, expected.1
+            ),
+            Self::Carve(CarveError::InvalidSigma(sigma)) => {
+                let max = Gaussian::MAX_SIGMA;
+                write!(
+                    f,
+                    "--blur-sigma must be positive and at most {max}, found {sigma}"
+                )
+            }
+            Self::Carve(err) => write!(f, "cannot carve the image: {err}"),
+        }
+    }
+}
+
+impl From<CarveError> for Failure {
+    fn from(err: CarveError) -> Self {
+        Self::Carve(err)
+    }
+}
+
+fn read_image(path: &str) -> Result<DynamicImage, Failure> {
+    let reader = ImageReader::open(path).map_err(|err| Failure::Read(path.into(), err.into()))?;
+    reader
+        .decode()
+        .map_err(|err| Failure::Read(path.into(), err))
+}
+
+fn read_energy_map(path: &str) -> Result<EnergyMap, Failure> {
+    if Path::new(path).extension().is_some_and(|ext| ext == "npy") {
+        let bytes = fs::read(path).map_err(|err| Failure::Read(path.into(), err.into()))?;
+        return parse_npy(&bytes).ok_or_else(|| Failure::Npy(path.into()));
+    }
+    Ok(read_image(path)?.into())
+}
+
+/// Parse a little-endian `f4`, `f8` or `u1` array of two dimensions in C
+/// order. `u1` samples are scaled to 0..1, like those of an image.
+fn parse_npy(bytes: &[u8]) -> Option<EnergyMap> {
+    let rest = bytes.strip_prefix(b"\x93NUMPY")?;
+    let (major, rest) = (*rest.first()?, rest.get(2..)?);
+    // Version 1 stores the header length on 2 bytes, later ones on 4
+    let size = if major == 1 { 2 } else { 4 };
+    let len = rest
+        .get(..size)?
+        .iter()
+        .rev()
+        .fold(0, |len, &b| len << 8 | b as usize);
+    let rest = &rest[size..];
+    let header = std::str::from_utf8(rest.get(..len)?).ok()?;
+    let data = &rest[len..];
+    let field = |key: &str| {
+        let (_, value) = header.split_once(&format!("'{key}':"))?;
+        Some(value.trim_start())
+    };
+    if !field("fortran_order")?.starts_with("False") {
+        return None;
+    }
+    let descr = field("descr")?.split('\'').nth(1)?;
+    let shape = field("shape")?.strip_prefix('(')?;
+    let shape: Vec<usize> = shape[..shape.find(')')?]
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().ok())
+        .collect::<Option<_>>()?;
+    let [height, width] = shape[..] else {
+        return None;
+    };
+    let itemsize = match descr {
+        "<f4" => 4,
+        "<f8" => 8,
+        "|u1" => 1,
+        _ => return None,
+    };
+    // A truncated or padded file would shift or drop values
+    if data.len() != width.checked_mul(height)?.checked_mul(itemsize)? {
+        return None;
+    }
+    let values = match descr {
+        "<f4" => data
+            .chunks_exact(4)
+            .map(|v| f32::from_le_bytes(v.try_into().unwrap()))
+            .collect(),
+        "<f8" => data
+            .chunks_exact(8)
+            .map(|v| f64::from_le_bytes(v.try_into().unwrap()) as f32)
+            .collect(),
+        "|u1" => data.iter().map(|&v| v as f32 / 255.0).collect(),
+        _ => return None,
+    };
+    EnergyMap::new(width, height, values).ok()
+}
+
+fn run(args: Args) -> Result<(), Failure> {
+    let img = read_image(&args.path)?;
     let width = img.width() as usize;
     let height = img.height() as usize;
     let new_width = (width as f32 * args.width_ratio) as usize;
     let new_height = (height as f32 * args.height_ratio) as usize;
 
-    let new_img = SeamCarver::new(img, new_width, new_height).apply();
-    let fname = match args.output{
-        Some(out) => out,
-        None => {
-            let path_buf = PathBuf::from(args.path);
-            let dir = path_buf.parent().unwrap().to_str().unwrap();
-            let fname = path_buf.file_stem().unwrap().to_str().unwrap();
-            format!("{dir}/{fname}_seamed.png")
-        }
+    let energy_mode = match args.forward {
+        true => EnergyMode::Forward,
+        false => EnergyMode::Backward,
+    };
+    let carver = SeamCarver::new(img, new_width, new_height)?
+        .energy_mode(energy_mode)
+        .order(args.order.into())
+        .normalize(match args.normalize {
+            NormalizeArg::None => Normalize::None,
+            NormalizeArg::MinMax => Normalize::MinMax,
+            NormalizeArg::Percentile => Normalize::Percentile(args.percentile.unwrap_or(0.99)),
+            NormalizeArg::Log => Normalize::Log,
+        });
+    let kernel = args.kernel.unwrap_or(Kernel::X3).into();
+    let border = args.border.unwrap_or(BorderArg::Truncate).into();
+    let window = args.window.unwrap_or(9);
+    let sobel = Sobel::new().kernel(kernel).border(border);
+    let mut carver = match args.energy {
+        EnergyFn::Sobel => carver.energy(sobel),
+        EnergyFn::L1 => carver.energy(L1Gradient),
+        EnergyFn::Dual => carver.energy(DualGradient),
+        EnergyFn::Color => carver.energy(ColorGradient::new()),
+        EnergyFn::Lab => carver.energy(ColorGradient::new().space(ColorSpace::Lab)),
+        EnergyFn::Entropy => carver.energy(Entropy::new().window(window).gradient(true)),
+        EnergyFn::EntropyOnly => carver.energy(Entropy::new().window(window)),
+        EnergyFn::Hog => carver.energy(
+            Hog::new()
+                .kernel(kernel)
+                .border(border)
+                .cell(args.cell.unwrap_or(11))
+                .bins(args.bins.unwrap_or(8)),
+        ),
+        EnergyFn::Saliency => carver.energy(Saliency::new()),
+        EnergyFn::SaliencySobel => carver.energy(Saliency::new().sobel(sobel)),
+    };
+    if let Some(sigma) = args.blur_sigma {
+        let gaussian = Gaussian::new(sigma)?;
+        carver = carver.blur(match args.blur_size {
+            Some(size) => gaussian.size(size),
+            None => gaussian,
+        });
+    }
+    if let Some(mask_path) = &args.remove_mask {
+        let mask = read_image(mask_path)?;
+        carver = carver.remove_mask(mask.into_luma8())?;
+    }
+    if let Some(mask_path) = &args.protect {
+        let mask = read_image(mask_path)?;
+        carver = carver.protect_mask(mask.into_luma8())?;
+    }
+    if let Some(map_path) = &args.energy_map {
+        let map = read_energy_map(map_path)?;
+        let blend = match args.map_weight {
+            Some(weight) => MapBlend::Add(weight),
+            None => MapBlend::Replace,
+        };
+        carver = carver.energy_map(map, blend)?;
+    }
+    let new_img = carver.apply()?;
+    let fname = match args.output {
+        Some(out) => PathBuf::from(out),
+        None => seamed_path(&args.path)?,
     };
-    new_img.save(fname).unwrap();
+    new_img
+        .save(&fname)
+        .map_err(|err| Failure::Write(fname.display().to_string(), err))
+}
+
+/// `<stem>_seamed.png` next to the input
+fn seamed_path(path: &str) -> Result<PathBuf, Failure> {
+    let path = Path::new(path);
+    match path.file_stem() {
+        Some(stem) => Ok(path.with_file_name(format!("{}_seamed.png", stem.to_string_lossy()))),
+        None => Err(Failure::Output(path.display().to_string())),
+    }
+}
+
+/// Reject the energy options the chosen energy would not use
+fn check_energy_options(args: &Args) -> Result<(), String> {
+    let uses_kernel = matches!(
+        args.energy,
+        EnergyFn::Sobel | EnergyFn::Hog | EnergyFn::SaliencySobel
+    );
+    let uses_window = matches!(args.energy, EnergyFn::Entropy | EnergyFn::EntropyOnly);
+    let uses_cells = matches!(args.energy, EnergyFn::Hog);
+    let options = [
+        ("--kernel", args.kernel.is_some(), uses_kernel),
+        ("--border", args.border.is_some(), uses_kernel),
+        ("--window", args.window.is_some(), uses_window),
+        ("--cell", args.cell.is_some(), uses_cells),
+        ("--bins", args.bins.is_some(), uses_cells),
+    ];
+    if let Some((name, _, _)) = options.iter().find(|(_, set, used)| *set && !used) {
+        let energy = args.energy.to_possible_value().unwrap();
+        return Err(format!(
+            "{name} does not apply to --energy {}",
+            energy.get_name()
+        ));
+    }
+    if args.percentile.is_some() && !matches!(args.normalize, NormalizeArg::Percentile) {
+        return Err("--percentile only applies to --normalize percentile".to_string());
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut cmd = Args::command();
+    let args = Args::from_arg_matches(&cmd.get_matches_mut()).unwrap_or_else(|err| err.exit());
+    if let Err(msg) = check_energy_options(&args) {
+        cmd.error(ErrorKind::ArgumentConflict, msg).exit();
+    }
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("error: {failure}");
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-}
\ No newline at end of file
+    fn npy(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_npy() {
+        let data: Vec<u8> = [0.5f32, 1.0, 2.0, 4.0, 8.0, 16.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let map = parse_npy(&npy("<f4", "(2, 3)", &data)).unwrap();
+        assert_eq!((3, 2), (map.width(), map.height()));
+        assert_eq!(&[0.5, 1.0, 2.0, 4.0, 8.0, 16.0], map.as_slice());
+
+        let data: Vec<u8> = [3.0f64, 1.5].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let map = parse_npy(&npy("<f8", "(1, 2)", &data)).unwrap();
+        assert_eq!(&[3.0, 1.5], map.as_slice());
+
+        let map = parse_npy(&npy("|u1", "(2, 1)", &[7, 255])).unwrap();
+        assert_eq!((1, 2), (map.width(), map.height()));
+        assert_eq!(&[7.0 / 255.0, 1.0], map.as_slice());
+
+        assert!(parse_npy(&npy("|u1", "(3,)", &[1, 2, 3])).is_none());
+        assert!(parse_npy(&npy("|u1", "(2, 2)", &[1, 2, 3])).is_none());
+        assert!(parse_npy(&npy("<f4", "(1, 2)", &[0; 9])).is_none());
+        assert!(parse_npy(&npy("<f4", "(1, 2)", &[0; 7])).is_none());
+        assert!(parse_npy(&npy("<f8", "(1, 1)", &[0; 12])).is_none());
+        assert!(parse_npy(&npy("<i4", "(1, 1)", &[0; 4])).is_none());
+        assert!(parse_npy(b"P5 1 1 255").is_none());
+    }
+
+    #[test]
+    fn test_npy_map_matches_image() {
+        let data: Vec<u8> = (0..12).map(|v| v * 23).collect();
+        let npy_map = parse_npy(&npy("|u1", "(3, 4)", &data)).unwrap();
+        let img = image::GrayImage::from_raw(4, 3, data).unwrap();
+        let img_map = EnergyMap::from(DynamicImage::ImageLuma8(img));
+        assert_eq!(img_map.as_slice(), npy_map.as_slice());
+    }
+
+    #[test]
+    fn test_seamed_path() {
+        assert_eq!(PathBuf::from("s_seamed.png"), seamed_path("s.jpg").unwrap());
+        let path = seamed_path("dir/s.tar.png").unwrap();
+        assert_eq!(PathBuf::from("dir/s.tar_seamed.png"), path);
+        assert_eq!(PathBuf::from("/s_seamed.png"), seamed_path("/s").unwrap());
+        assert!(seamed_path("/").is_err());
+    }
+
+    #[test]
+    fn test_check_energy_options() {
+        let args = |extra: &[&str]| Args::try_parse_from(["sc", "a.png"].iter().chain(extra));
+        let check = |extra: &[&str]| check_energy_options(&args(extra).unwrap());
+        assert!(check(&["-k", "5"]).is_ok());
+        assert!(check(&["-e", "hog", "-k", "5"]).is_ok());
+        assert!(check(&["-e", "color"]).is_ok());
+        assert!(check(&["-e", "color", "-k", "5"]).is_err());
+        assert!(check(&["-e", "saliency", "-k", "7"]).is_err());
+        assert!(args(&["-f", "-k", "5"]).is_err());
+
+        assert!(check(&["-e", "saliency-sobel", "--border", "reflect"]).is_ok());
+        assert!(check(&["-e", "color", "--border", "reflect"]).is_err());
+        assert!(check(&["-e", "entropy-only", "--window", "3"]).is_ok());
+        assert!(check(&["-e", "sobel", "--window", "3"]).is_err());
+        assert!(check(&["-e", "hog", "--cell", "5", "--bins", "4"]).is_ok());
+        assert!(check(&["--cell", "5"]).is_err());
+        assert!(check(&["-e", "entropy", "--bins", "4"]).is_err());
+        assert!(check(&["--normalize", "percentile", "--percentile", "0.9"]).is_ok());
+        assert!(check(&["--percentile", "0.9"]).is_err());
+        assert!(check(&["--normalize", "min-max", "--percentile", "0.9"]).is_err());
+    }
+}
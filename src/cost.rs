@@ -4,6 +4,17 @@ pub enum Direction {
     Column,
 }
 
+/// How the cost of removing a pixel is accumulated along a seam
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnergyMode {
+    /// Sum the precomputed energy of the removed pixels
+    #[default]
+    Backward,
+    /// Sum the gradients created between the pixels that become neighbours
+    /// once the seam is removed (Rubinstein et al. 2008)
+    Forward,
+}
+
 #[derive(Debug)]
 struct MapState {
     outer: usize,
@@ -25,9 +36,41 @@ impl MapState {
             offset,
         }
     }
+
+    #[inline]
+    fn index(&self, line: usize, pos: usize) -> usize {
+        line * self.stride + pos * self.offset
+    }
 }
 
+/// Lines shorter than this are filled faster by a single thread
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_LINE: usize = 2048;
+
+/// The pixels of a line given to each thread
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK: usize = 1024;
+
 pub fn build_cost_matrix(energy: &[f32], width: usize, height: usize, dir: Direction) -> Vec<f32> {
+    #[cfg(feature = "parallel")]
+    {
+        let line = match dir {
+            Direction::Row => width,
+            Direction::Column => height,
+        };
+        if line >= PARALLEL_MIN_LINE && rayon::current_num_threads() > 1 {
+            return build_cost_matrix_parallel(energy, width, height, dir);
+        }
+    }
+    build_cost_matrix_serial(energy, width, height, dir)
+}
+
+fn build_cost_matrix_serial(
+    energy: &[f32],
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> Vec<f32> {
     let mut res = vec![0.0; energy.len()];
     let state = MapState::from_dir(width, height, dir);
     // Copy the last row from the energy matrix
@@ -37,6 +80,15 @@ pub fn build_cost_matrix(energy: &[f32], width: usize, height: usize, dir: Direc
         idx += state.offset;
     }
 
+    // With a single pixel per line the seam has nowhere to go but straight
+    if state.inner == 1 {
+        for line in (0..state.outer - 1).rev() {
+            idx = line * state.stride;
+            res[idx] = energy[idx] + res[idx + state.stride];
+        }
+        return res;
+    }
+
     // Loop over one col/row
     idx -= state.offset;
     for _ in 0..(state.outer - 1) {
@@ -62,6 +114,69 @@ pub fn build_cost_matrix(energy: &[f32], width: usize, height: usize, dir: Direc
     res
 }
 
+/// Every cell of a line only depends on the line after it, so each line is
+/// split across threads. Columns are transposed first to work on contiguous
+/// lines. The cells are computed as in [`build_cost_matrix_serial`], so the
+/// results are the same to the bit.
+#[cfg(feature = "parallel")]
+fn build_cost_matrix_parallel(
+    energy: &[f32],
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> Vec<f32> {
+    use rayon::prelude::*;
+
+    if let Direction::Column = dir {
+        let energy = transpose(energy, 1, width, height);
+        let res = build_cost_matrix_parallel(&energy, height, width, Direction::Row);
+        return transpose(&res, 1, height, width);
+    }
+    let mut res = vec![0.0; energy.len()];
+    let last = (height - 1) * width;
+    res[last..].copy_from_slice(&energy[last..]);
+    for line in (0..height - 1).rev() {
+        let (res, next) = res.split_at_mut((line + 1) * width);
+        let next = &next[..width];
+        let energy = &energy[line * width..(line + 1) * width];
+        res[line * width..]
+            .par_chunks_mut(PARALLEL_CHUNK)
+            .enumerate()
+            .for_each(|(chunk, res)| {
+                for (pos, val) in (chunk * PARALLEL_CHUNK..).zip(res) {
+                    let parents = if width == 1 {
+                        next[0]
+                    } else if pos == 0 {
+                        min2(next[0], next[1])
+                    } else if pos == width - 1 {
+                        min2(next[pos - 1], next[pos])
+                    } else {
+                        min3(next[pos - 1], next[pos], next[pos + 1])
+                    };
+                    *val = energy[pos] + parents;
+                }
+            });
+    }
+    res
+}
+
+/// Swap the rows and columns of an image of interleaved `channels`
+pub(crate) fn transpose<T: Copy>(
+    buf: &[T],
+    channels: usize,
+    width: usize,
+    height: usize,
+) -> Vec<T> {
+    let mut res = Vec::with_capacity(buf.len());
+    for c in 0..width {
+        for r in 0..height {
+            let idx = (c + r * width) * channels;
+            res.extend_from_slice(&buf[idx..idx + channels]);
+        }
+    }
+    res
+}
+
 pub fn find_shortest_path(cost: &[f32], width: usize, height: usize, dir: Direction) -> Vec<usize> {
     let state = MapState::from_dir(width, height, dir);
     let mut res = Vec::with_capacity(state.outer);
@@ -83,7 +198,7 @@ pub fn find_shortest_path(cost: &[f32], width: usize, height: usize, dir: Direct
         idx = min_idx + state.stride;
         min_idx = idx;
         cur_min = cost[idx];
-        if (idx / state.offset) % state.inner != 0 {
+        if !(idx / state.offset).is_multiple_of(state.inner) {
             let o_idx = idx - state.offset;
             let val = cost[o_idx];
             if cur_min > val {
@@ -104,6 +219,111 @@ pub fn find_shortest_path(cost: &[f32], width: usize, height: usize, dir: Direct
     res
 }
 
+/// `pixel_energy` is added to the cost of each pixel on top of the forward
+/// energy, e.g. to steer seams towards or away from masked regions.
+pub fn build_forward_cost_matrix(
+    gray: &[u8],
+    pixel_energy: Option<&[f32]>,
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> Vec<f32> {
+    let mut res = match pixel_energy {
+        Some(energy) => energy.to_vec(),
+        None => vec![0.0; gray.len()],
+    };
+    let state = MapState::from_dir(width, height, dir);
+    // The last row has no successor, only its neighbours become adjacent
+    let last = state.outer - 1;
+    for pos in 0..state.inner {
+        let (left, right) = forward_neighbours(gray, &state, last, pos);
+        res[state.index(last, pos)] += (right - left).abs();
+    }
+
+    for line in (0..last).rev() {
+        for pos in 0..state.inner {
+            let (c_l, c_u, c_r) = forward_costs(gray, &state, line, pos);
+            let mut val = res[state.index(line + 1, pos)] + c_u;
+            if pos > 0 {
+                val = min2(val, res[state.index(line + 1, pos - 1)] + c_l);
+            }
+            if pos < state.inner - 1 {
+                val = min2(val, res[state.index(line + 1, pos + 1)] + c_r);
+            }
+            res[state.index(line, pos)] += val;
+        }
+    }
+
+    res
+}
+
+pub fn find_forward_shortest_path(
+    cost: &[f32],
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> Vec<usize> {
+    let state = MapState::from_dir(width, height, dir);
+    let mut res = Vec::with_capacity(state.outer);
+
+    let mut cur_min = f32::MAX;
+    let mut pos = 0;
+    for p in 0..state.inner {
+        let val = cost[state.index(0, p)];
+        if cur_min > val {
+            pos = p;
+            cur_min = val;
+        }
+    }
+    res.push(state.index(0, pos));
+
+    for line in 0..state.outer - 1 {
+        let (c_l, c_u, c_r) = forward_costs(gray, &state, line, pos);
+        let center = pos;
+        cur_min = cost[state.index(line + 1, center)] + c_u;
+        if center > 0 {
+            let val = cost[state.index(line + 1, center - 1)] + c_l;
+            if cur_min > val {
+                pos = center - 1;
+                cur_min = val;
+            }
+        }
+        if center < state.inner - 1 {
+            let val = cost[state.index(line + 1, center + 1)] + c_r;
+            if cur_min > val {
+                pos = center + 1;
+            }
+        }
+        res.push(state.index(line + 1, pos));
+    }
+
+    res
+}
+
+/// The values on both sides of a pixel, replicating the border
+#[inline]
+fn forward_neighbours(gray: &[u8], state: &MapState, line: usize, pos: usize) -> (f32, f32) {
+    let left = if pos > 0 { pos - 1 } else { pos };
+    let right = if pos < state.inner - 1 { pos + 1 } else { pos };
+    (
+        gray[state.index(line, left)] as f32,
+        gray[state.index(line, right)] as f32,
+    )
+}
+
+/// The cost of removing a pixel when the seam continues to the previous,
+/// same or next position of the following line.
+#[inline]
+fn forward_costs(gray: &[u8], state: &MapState, line: usize, pos: usize) -> (f32, f32, f32) {
+    let (left, right) = forward_neighbours(gray, state, line, pos);
+    let below = gray[state.index(line + 1, pos)] as f32;
+    let c_u = (right - left).abs();
+    let c_l = c_u + (below - left).abs();
+    let c_r = c_u + (below - right).abs();
+    (c_l, c_u, c_r)
+}
+
 #[inline]
 fn min2(v1: f32, v2: f32) -> f32 {
     if v1 < v2 {
@@ -134,6 +354,70 @@ mod tests {
         assert_eq!(energy, costs);
     }
 
+    #[rstest]
+    fn test_single_pixel_lines(
+        #[values(Direction::Row, Direction::Column)] dir: Direction,
+        #[values((1, 4), (4, 1), (1, 1))] dims: (usize, usize),
+    ) {
+        let (width, height) = dims;
+        let energy: Vec<f32> = (0..width * height).map(|v| (v % 3) as f32).collect();
+        let gray: Vec<u8> = (0..width * height).map(|v| (v * 7 % 5) as u8).collect();
+        let costs = build_cost_matrix(&energy, width, height, dir);
+        let path = find_shortest_path(&costs, width, height, dir);
+        let forward = build_forward_cost_matrix(&gray, None, width, height, dir);
+        let forward_path = find_forward_shortest_path(&forward, &gray, width, height, dir);
+        let (outer, inner) = match dir {
+            Direction::Row => (height, width),
+            Direction::Column => (width, height),
+        };
+        assert_eq!(outer, path.len());
+        assert_eq!(outer, forward_path.len());
+        if inner == 1 {
+            // Every line has a single pixel, so the seam goes through all
+            let all: Vec<usize> = (0..outer).collect();
+            assert_eq!(all, path);
+            assert_eq!(all, forward_path);
+            assert_eq!(energy.iter().sum::<f32>(), costs[0]);
+            assert_eq!(0.0, forward[0]);
+        } else {
+            // A single line, the cost is the energy itself
+            assert_eq!(energy, costs);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[rstest]
+    fn test_parallel_cost_matrix(
+        #[values(Direction::Row, Direction::Column)] dir: Direction,
+        #[values((1, 9), (9, 1), (2500, 7), (7, 2500), (1030, 300))] dims: (usize, usize),
+    ) {
+        let (width, height) = dims;
+        let energy: Vec<f32> = (0..width * height)
+            .map(|v| (v * 7919 % 1013) as f32 / 7.0)
+            .collect();
+        let serial = build_cost_matrix_serial(&energy, width, height, dir);
+        let parallel = build_cost_matrix_parallel(&energy, width, height, dir);
+        assert!(serial
+            .iter()
+            .map(|v| v.to_bits())
+            .eq(parallel.iter().map(|v| v.to_bits())));
+    }
+
+    #[test]
+    fn test_transpose() {
+        #[rustfmt::skip]
+        let rgb = [
+            1, 1, 1, 2, 2, 2, 3, 3, 3,
+            4, 4, 4, 5, 5, 5, 6, 6, 6,
+        ];
+        let transposed = transpose(&rgb, 3, 3, 2);
+        assert_eq!(
+            vec![1, 1, 1, 4, 4, 4, 2, 2, 2, 5, 5, 5, 3, 3, 3, 6, 6, 6],
+            transposed
+        );
+        assert_eq!(rgb.to_vec(), transpose(&transposed, 3, 2, 3));
+    }
+
     #[test]
     fn test_build_cost_01_row() {
         let w = 3;
@@ -199,6 +483,87 @@ mod tests {
         assert_eq!(expected, costs);
     }
 
+    #[rstest]
+    fn test_build_forward_cost_0_case(
+        #[values(Direction::Row, Direction::Column)] dir: Direction,
+        #[values(3, 4, 5)] width: usize,
+        #[values(3, 4, 5)] height: usize,
+    ) {
+        let gray = vec![7; width * height];
+        let costs = build_forward_cost_matrix(&gray, None, width, height, dir);
+        assert_eq!(vec![0.0; width * height], costs);
+    }
+
+    #[test]
+    fn test_build_forward_cost_01() {
+        let w = 4;
+        let h = 3;
+        #[rustfmt::skip]
+        let gray = vec![
+            0, 0, 9, 9,
+            0, 0, 9, 9,
+            0, 0, 9, 9,
+        ];
+        #[rustfmt::skip]
+        let expected = vec![
+            0., 9., 9., 0.,
+            0., 9., 9., 0.,
+            0., 9., 9., 0.,
+        ];
+        let costs = build_forward_cost_matrix(&gray, None, w, h, Direction::Row);
+        assert_eq!(expected, costs);
+        let path = find_forward_shortest_path(&costs, &gray, w, h, Direction::Row);
+        assert_eq!(vec![0, 4, 8], path);
+
+        let costs = build_forward_cost_matrix(&gray, None, w, h, Direction::Column);
+        assert_eq!(vec![0.0; w * h], costs);
+        let path = find_forward_shortest_path(&costs, &gray, w, h, Direction::Column);
+        assert_eq!(vec![0, 1, 2, 3], path);
+    }
+
+    /// Sum the gradients created by removing `seam` from `gray`, one
+    /// position per row.
+    fn seam_forward_energy(gray: &[u8], width: usize, seam: &[usize]) -> f32 {
+        let at = |r: usize, c: usize| gray[r * width + c] as f32;
+        let mut res = 0.0;
+        for (r, &c) in seam.iter().enumerate() {
+            let left = at(r, c.saturating_sub(1));
+            let right = at(r, (c + 1).min(width - 1));
+            res += (right - left).abs();
+            match seam.get(r + 1) {
+                Some(&n) if n + 1 == c => res += (at(r + 1, c) - left).abs(),
+                Some(&n) if n == c + 1 => res += (at(r + 1, c) - right).abs(),
+                _ => {}
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_find_forward_path_is_optimal() {
+        let w: usize = 4;
+        let h: usize = 4;
+        #[rustfmt::skip]
+        let gray = vec![
+            0, 9, 9, 9,
+            5, 0, 9, 7,
+            5, 5, 0, 9,
+            3, 5, 5, 0,
+        ];
+        let mut best = f32::MAX;
+        for seam in 0..w.pow(h as u32) {
+            let seam: Vec<usize> = (0..h as u32).map(|r| seam / w.pow(r) % w).collect();
+            if seam.windows(2).all(|p| p[0].abs_diff(p[1]) <= 1) {
+                best = best.min(seam_forward_energy(&gray, w, &seam));
+            }
+        }
+        let costs = build_forward_cost_matrix(&gray, None, w, h, Direction::Row);
+        let path = find_forward_shortest_path(&costs, &gray, w, h, Direction::Row);
+        let seam: Vec<usize> = path.iter().map(|idx| idx % w).collect();
+        assert_eq!(best, costs[path[0]]);
+        assert_eq!(best, seam_forward_energy(&gray, w, &seam));
+    }
+
     #[test]
     fn test_test_find_path_01() {
         let w = 5;
@@ -0,0 +1,679 @@
+use std::{
+    cmp::{max, min},
+    ops::Range,
+};
+
+use crate::sobel::{Kernel, Sobel};
+
+/// Computes the importance of every pixel of an image, seams go through the
+/// pixels with the lowest energy.
+///
+/// `image` holds `width` x `height` pixels row by row, each one made of
+/// [`Energy::channels`] interleaved bytes: the grayscale image when it is
+/// 1, the RGB one when it is 3. The energy holds one value per pixel, row by
+/// row as well.
+///
+/// Implement at least one of [`Energy::energy`] and [`Energy::update`],
+/// each defaults to the other.
+pub trait Energy {
+    /// The energy of the whole image, by default updating every pixel of
+    /// a zeroed map
+    fn energy(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        let mut buf = vec![0.0; width * height];
+        self.update(image, width, height, &mut buf, 0..width, 0..height);
+        buf
+    }
+
+    /// The number of interleaved channels of `image`: 1 for the grayscale
+    /// image, 3 for the RGB one
+    fn channels(&self) -> usize {
+        1
+    }
+
+    /// The distance up to which a pixel influences the energy of its
+    /// neighbours, `None` if any pixel may influence the whole map. Local
+    /// energies can be updated around a removed seam only.
+    fn radius(&self) -> Option<usize> {
+        None
+    }
+
+    /// The smallest width and height the energy can be computed on. The
+    /// built-in energies truncate their windows at the border, so they run
+    /// on any image.
+    fn min_size(&self) -> usize {
+        1
+    }
+
+    /// Switch the energy to `kernel` if it is built on one. Returns whether
+    /// it is, energies without a kernel are left untouched.
+    fn set_kernel(&mut self, _kernel: Kernel) -> bool {
+        false
+    }
+
+    /// Whether the energy of the transposed image is the transposed
+    /// energy, which lets [`Layout::Transposed`](crate::seam::Layout::Transposed)
+    /// carve horizontal seams along rows. Energies returning false, the
+    /// default, are always walked in [`Layout::Strided`](crate::seam::Layout::Strided).
+    fn orientation_invariant(&self) -> bool {
+        false
+    }
+
+    /// Recompute the energy of the pixels in `cols` x `rows` only, leaving
+    /// the rest of `buf` untouched. By default the whole energy is computed
+    /// and the region copied out of it.
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        update_from_energy(self, image, width, height, buf, cols, rows)
+    }
+}
+
+/// The default [`Energy::update`]: compute the whole energy and copy the
+/// pixels in `cols` x `rows` out of it
+pub(crate) fn update_from_energy<E: Energy + ?Sized>(
+    energy: &E,
+    image: &[u8],
+    width: usize,
+    height: usize,
+    buf: &mut [f32],
+    cols: Range<usize>,
+    rows: Range<usize>,
+) {
+    let energy = energy.energy(image, width, height);
+    for r in rows {
+        let line = r * width;
+        buf[line + cols.start..line + cols.end]
+            .copy_from_slice(&energy[line + cols.start..line + cols.end]);
+    }
+}
+
+impl<E: Energy + ?Sized> Energy for Box<E> {
+    fn energy(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        (**self).energy(image, width, height)
+    }
+
+    fn channels(&self) -> usize {
+        (**self).channels()
+    }
+
+    fn radius(&self) -> Option<usize> {
+        (**self).radius()
+    }
+
+    fn min_size(&self) -> usize {
+        (**self).min_size()
+    }
+
+    fn set_kernel(&mut self, kernel: Kernel) -> bool {
+        (**self).set_kernel(kernel)
+    }
+
+    fn orientation_invariant(&self) -> bool {
+        (**self).orientation_invariant()
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        (**self).update(image, width, height, buf, cols, rows)
+    }
+}
+
+/// The paper's e1 energy, the sum of the absolute horizontal and vertical
+/// central differences
+#[derive(Debug, Clone, Copy, Default)]
+pub struct L1Gradient;
+
+impl Energy for L1Gradient {
+    fn orientation_invariant(&self) -> bool {
+        true
+    }
+
+    fn radius(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        for r in rows {
+            for c in cols.clone() {
+                let (dx, dy) = central_differences(image, width, height, c, r);
+                buf[c + r * width] = dx.abs() + dy.abs();
+            }
+        }
+    }
+}
+
+/// The magnitude of the central differences gradient
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DualGradient;
+
+impl Energy for DualGradient {
+    fn orientation_invariant(&self) -> bool {
+        true
+    }
+
+    fn radius(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        for r in rows {
+            for c in cols.clone() {
+                let (dx, dy) = central_differences(image, width, height, c, r);
+                buf[c + r * width] = (dx * dx + dy * dy).sqrt();
+            }
+        }
+    }
+}
+
+/// Shannon entropy, in bits, of the histogram of a square window around
+/// every pixel. High in textured regions such as foliage, which gradient
+/// energies let seams wander through. With `gradient` the [`L1Gradient`] is
+/// added, the paper's e_entropy.
+#[derive(Debug, Clone, Copy)]
+pub struct Entropy {
+    window: usize,
+    gradient: bool,
+}
+
+impl Entropy {
+    pub fn new() -> Self {
+        Self {
+            window: 9,
+            gradient: false,
+        }
+    }
+
+    /// The side of the window, even sizes are rounded up
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn gradient(mut self, gradient: bool) -> Self {
+        self.gradient = gradient;
+        self
+    }
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Energy for Entropy {
+    fn radius(&self) -> Option<usize> {
+        Some(max(self.window / 2, self.gradient as usize))
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        let b = self.window / 2;
+        let mut counts = [0u32; 256];
+        for r in rows {
+            let window_rows = r.saturating_sub(b)..min(r + b + 1, height);
+            for c in cols.clone() {
+                let window_cols = c.saturating_sub(b)..min(c + b + 1, width);
+                let window = window_rows.clone().flat_map(|r| {
+                    &image[r * width + window_cols.start..r * width + window_cols.end]
+                });
+                for &v in window.clone() {
+                    counts[v as usize] += 1;
+                }
+                // Visit every value once, resetting the counts on the way
+                let n = (window_rows.len() * window_cols.len()) as f32;
+                let mut entropy = 0.0;
+                for &v in window {
+                    let count = std::mem::take(&mut counts[v as usize]);
+                    if count > 0 {
+                        let p = count as f32 / n;
+                        entropy -= p * p.log2();
+                    }
+                }
+                if self.gradient {
+                    let (dx, dy) = central_differences(image, width, height, c, r);
+                    entropy += dx.abs() + dy.abs();
+                }
+                buf[c + r * width] = entropy;
+            }
+        }
+    }
+}
+
+/// How the gradients of the color channels are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Combine {
+    /// The sum of the gradient magnitudes
+    #[default]
+    Sum,
+    /// The largest gradient magnitude
+    Max,
+    /// The magnitude of the gradient of the color vector
+    L2,
+}
+
+/// The color space the gradients are computed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    Rgb,
+    /// CIELAB under D65, where distances follow perceived differences
+    Lab,
+}
+
+/// The central differences gradient of every color channel, combined into
+/// one energy. Unlike grayscale energies it sees edges between colors of
+/// the same luminance, such as red on green.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColorGradient {
+    combine: Combine,
+    space: ColorSpace,
+}
+
+impl ColorGradient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn combine(mut self, combine: Combine) -> Self {
+        self.combine = combine;
+        self
+    }
+
+    pub fn space(mut self, space: ColorSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Energy for ColorGradient {
+    fn orientation_invariant(&self) -> bool {
+        true
+    }
+
+    fn channels(&self) -> usize {
+        3
+    }
+
+    fn radius(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        let linear = srgb_to_linear();
+        let at = |c: usize, r: usize| {
+            let idx = 3 * (c + r * width);
+            let rgb = [image[idx], image[idx + 1], image[idx + 2]];
+            match self.space {
+                ColorSpace::Rgb => rgb.map(|v| v as f32),
+                ColorSpace::Lab => lab(rgb.map(|v| linear[v as usize])),
+            }
+        };
+        for r in rows {
+            for c in cols.clone() {
+                let (left, right) = (at(c.saturating_sub(1), r), at(min_next(c, width), r));
+                let (up, down) = (at(c, r.saturating_sub(1)), at(c, min_next(r, height)));
+                let squares =
+                    (0..3).map(|ch| (right[ch] - left[ch]).powi(2) + (down[ch] - up[ch]).powi(2));
+                buf[c + r * width] = match self.combine {
+                    Combine::Sum => squares.map(f32::sqrt).sum(),
+                    Combine::Max => squares.map(f32::sqrt).fold(0.0, f32::max),
+                    Combine::L2 => squares.sum::<f32>().sqrt(),
+                };
+            }
+        }
+    }
+}
+
+/// Frequency-tuned saliency (Achanta et al. 2009): the CIELAB distance of
+/// every pixel, slightly blurred, to the mean color of the image. Keeps the
+/// main subject of product shots and portraits whole where gradients alone
+/// do not. Depends on the whole image, so it is never updated locally.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Saliency {
+    sobel: Option<Sobel>,
+}
+
+impl Saliency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Multiply the saliency with the energy of `sobel` on the luma
+    pub fn sobel(mut self, sobel: Sobel) -> Self {
+        self.sobel = Some(sobel);
+        self
+    }
+}
+
+impl Energy for Saliency {
+    fn energy(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        let linear = srgb_to_linear();
+        let lab: Vec<[f32; 3]> = image
+            .chunks_exact(3)
+            .map(|p| lab([p[0], p[1], p[2]].map(|v| linear[v as usize])))
+            .collect();
+        let mut mean = [0.0; 3];
+        for p in lab.iter() {
+            for (m, v) in mean.iter_mut().zip(p) {
+                *m += v / lab.len() as f32;
+            }
+        }
+        let lab = binomial_blur(&lab, width, height);
+        let saliency = lab.iter().map(|p| {
+            let d: f32 = (0..3).map(|ch| (p[ch] - mean[ch]).powi(2)).sum();
+            d.sqrt()
+        });
+        match self.sobel {
+            Some(sobel) => {
+                let luma: Vec<u8> = image.chunks_exact(3).map(luma).collect();
+                let edges = sobel.apply(&luma, width, height);
+                saliency.zip(edges).map(|(s, e)| s * e).collect()
+            }
+            None => saliency.collect(),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        3
+    }
+
+    fn set_kernel(&mut self, kernel: Kernel) -> bool {
+        match self.sobel.as_mut() {
+            Some(sobel) => sobel.set_kernel(kernel),
+            None => false,
+        }
+    }
+}
+
+/// Blur with the 5x5 binomial kernel, replicating the border
+fn binomial_blur(src: &[[f32; 3]], width: usize, height: usize) -> Vec<[f32; 3]> {
+    const WEIGHTS: [f32; 5] = [1.0, 4.0, 6.0, 4.0, 1.0];
+    let add = |acc: &mut [f32; 3], w: f32, v: [f32; 3]| {
+        for (a, v) in acc.iter_mut().zip(v) {
+            *a += w * v / 16.0;
+        }
+    };
+    let mut rows = vec![[0.0; 3]; src.len()];
+    for r in 0..height {
+        for c in 0..width {
+            for (k, &w) in WEIGHTS.iter().enumerate() {
+                let x = (c + k).saturating_sub(2).min(width - 1);
+                add(&mut rows[c + r * width], w, src[x + r * width]);
+            }
+        }
+    }
+    let mut res = vec![[0.0; 3]; src.len()];
+    for r in 0..height {
+        for c in 0..width {
+            for (k, &w) in WEIGHTS.iter().enumerate() {
+                let y = (r + k).saturating_sub(2).min(height - 1);
+                add(&mut res[c + r * width], w, rows[c + y * width]);
+            }
+        }
+    }
+    res
+}
+
+/// The luma of an sRGB pixel, as computed by `DynamicImage::grayscale`
+#[inline]
+fn luma(rgb: &[u8]) -> u8 {
+    let (r, g, b) = (rgb[0] as u32, rgb[1] as u32, rgb[2] as u32);
+    ((2126 * r + 7152 * g + 722 * b) / 10000) as u8
+}
+
+/// Linear intensity of every 8 bit sRGB value
+fn srgb_to_linear() -> [f32; 256] {
+    std::array::from_fn(|v| {
+        let v = v as f32 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+/// CIELAB coordinates of a linear RGB color, with a D65 white point
+fn lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.119192 * g + 0.9503041 * b) / 1.08883;
+    let f = |t: f32| {
+        const DELTA: f32 = 6.0 / 29.0;
+        if t > DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Differences between the neighbours on both sides of a pixel, replicating
+/// the border
+#[inline]
+fn central_differences(
+    image: &[u8],
+    width: usize,
+    height: usize,
+    c: usize,
+    r: usize,
+) -> (f32, f32) {
+    let at = |c: usize, r: usize| image[c + r * width] as f32;
+    let dx = at(min_next(c, width), r) - at(c.saturating_sub(1), r);
+    let dy = at(c, min_next(r, height)) - at(c, r.saturating_sub(1));
+    (dx, dy)
+}
+
+#[inline]
+fn min_next(idx: usize, len: usize) -> usize {
+    if idx + 1 < len {
+        idx + 1
+    } else {
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(&L1Gradient, vec![0., 50., 90., 40., 0., 50., 90., 40.])]
+    #[case(&DualGradient, vec![0., 50., 90., 40., 0., 50., 90., 40.])]
+    fn test_horizontal_ramp(#[case] energy: &dyn Energy, #[case] expected: Vec<f32>) {
+        #[rustfmt::skip]
+        let image = vec![
+            0, 0, 50, 90,
+            0, 0, 50, 90,
+        ];
+        assert_eq!(expected, energy.energy(&image, 4, 2));
+    }
+
+    #[test]
+    fn test_l1_and_dual_gradient() {
+        #[rustfmt::skip]
+        let image = vec![
+            0, 0, 0,
+            0, 30, 40,
+            0, 40, 0,
+        ];
+        let l1 = L1Gradient.energy(&image, 3, 3);
+        let dual = DualGradient.energy(&image, 3, 3);
+        // Center pixel: dx = 40 - 0, dy = 40 - 0
+        assert_eq!(80.0, l1[4]);
+        assert_eq!(3200f32.sqrt(), dual[4]);
+    }
+
+    #[test]
+    fn test_entropy() {
+        // Two values mixed over the first rows, then a flat region
+        #[rustfmt::skip]
+        let image = vec![
+            0, 0, 1, 1, 0, 0,
+            1, 1, 0, 0, 1, 1,
+            7, 7, 7, 7, 7, 7,
+            7, 7, 7, 7, 7, 7,
+        ];
+        let energy = Entropy::new().window(3).energy(&image, 6, 4);
+        // Three 0, three 1 and three 7 around pixel (2, 1)
+        assert!((3f32.log2() - energy[2 + 6]).abs() < 1e-6);
+        // Two 0 and two 1 in the truncated window of the corner
+        assert_eq!(1.0, energy[0]);
+        assert_eq!(0.0, energy[3 * 6 + 1]);
+
+        let with_gradient = Entropy::new().window(3).gradient(true);
+        let gradient = L1Gradient.energy(&image, 6, 4);
+        let sum = with_gradient.energy(&image, 6, 4);
+        for ((s, e), g) in sum.iter().zip(energy).zip(gradient) {
+            assert_eq!(*s, e + g);
+        }
+    }
+
+    #[test]
+    fn test_saliency() {
+        // A red square on a gray background
+        let (width, height) = (20, 20);
+        let image: Vec<u8> = (0..width * height)
+            .flat_map(|idx| match (idx % width, idx / width) {
+                (6..=9, 6..=9) => [220, 30, 30],
+                _ => [128, 128, 128],
+            })
+            .collect();
+        let saliency = Saliency::new().energy(&image, width, height);
+        let background = saliency[1 + width];
+        assert!(saliency[7 + 7 * width] > 5.0 * background);
+        assert_eq!(background, saliency[18 + 18 * width]);
+
+        let sobel = Sobel::new();
+        let energy = Saliency::new().sobel(sobel).energy(&image, width, height);
+        let luma: Vec<u8> = image.chunks(3).map(luma).collect();
+        let edges = sobel.apply(&luma, width, height);
+        for ((e, s), g) in energy.iter().zip(saliency).zip(edges) {
+            assert_eq!(*e, s * g);
+        }
+    }
+
+    #[test]
+    fn test_luma_matches_grayscale() {
+        let image: Vec<u8> = (0..300u32).map(|v| (v * 97 % 256) as u8).collect();
+        let gray = image::RgbImage::from_raw(10, 10, image.clone()).unwrap();
+        let gray = image::DynamicImage::from(gray).grayscale().into_luma8();
+        let luma: Vec<u8> = image.chunks(3).map(luma).collect();
+        assert_eq!(gray.into_raw(), luma);
+    }
+
+    #[test]
+    fn test_lab() {
+        let linear = srgb_to_linear();
+        let white = lab([linear[255]; 3]);
+        assert!((white[0] - 100.0).abs() < 1e-2);
+        assert!(white[1].abs() < 1e-2 && white[2].abs() < 1e-2);
+        assert!(lab([linear[0]; 3]).iter().all(|v| v.abs() < 1e-4));
+        // Red is far along a, green far along -a
+        assert!(lab([1.0, 0.0, 0.0])[1] > 70.0);
+        assert!(lab([0.0, 1.0, 0.0])[1] < -70.0);
+    }
+
+    #[rstest]
+    fn test_color_edge_of_same_luminance(
+        #[values(Combine::Sum, Combine::Max, Combine::L2)] combine: Combine,
+        #[values(ColorSpace::Rgb, ColorSpace::Lab)] space: ColorSpace,
+    ) {
+        // Red next to a green of nearly the same luma
+        let (red, green) = ([200, 0, 0], [0, 59, 0]);
+        let image: Vec<u8> = (0..16)
+            .flat_map(|i| if i % 4 < 2 { red } else { green })
+            .collect();
+        let gray: Vec<u8> = image
+            .chunks(3)
+            .map(|p| ((p[0] as u32 * 2126 + p[1] as u32 * 7152) / 10000) as u8)
+            .collect();
+        assert!(DualGradient.energy(&gray, 4, 4).iter().all(|&e| e <= 2.0));
+
+        let energy = ColorGradient::new()
+            .combine(combine)
+            .space(space)
+            .energy(&image, 4, 4);
+        assert_eq!(16, energy.len());
+        for row in energy.chunks(4) {
+            assert_eq!(0.0, row[0]);
+            assert!(row[1] > 50.0 && row[2] > 50.0);
+            assert_eq!(0.0, row[3]);
+        }
+    }
+
+    #[test]
+    fn test_combine() {
+        let image = vec![0, 0, 0, 0, 0, 0, 30, 40, 0];
+        let energy = |combine| ColorGradient::new().combine(combine).energy(&image, 3, 1);
+        // The middle pixel sees dx = (30, 40, 0)
+        assert_eq!(70.0, energy(Combine::Sum)[1]);
+        assert_eq!(40.0, energy(Combine::Max)[1]);
+        assert_eq!(50.0, energy(Combine::L2)[1]);
+    }
+
+    #[rstest]
+    fn test_update_matches_energy(
+        #[values(
+            &L1Gradient as &dyn Energy,
+            &DualGradient as &dyn Energy,
+            &Entropy::new().window(5) as &dyn Energy,
+            &Entropy::new().window(4).gradient(true) as &dyn Energy
+        )]
+        energy: &dyn Energy,
+    ) {
+        let image: Vec<u8> = (0..35).map(|v| (v * 37 % 256) as u8).collect();
+        let expected = energy.energy(&image, 7, 5);
+        let mut buf = vec![-1.0; 35];
+        energy.update(&image, 7, 5, &mut buf, 0..7, 0..5);
+        assert_eq!(expected, buf);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{error::Error, fmt};
+
+use image::ColorType;
+
+use crate::filter::Gaussian;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CarveError {
+    /// A mask or buffer does not have the dimensions it should
+    InvalidDimensions {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// A buffer does not hold one value per pixel
+    InvalidBufferLength { expected: usize, found: usize },
+    /// The image's pixel type cannot be carved
+    UnsupportedPixelType(ColorType),
+    /// The target width or height is zero
+    ZeroSizeTarget,
+    /// The image is smaller than the energy can be computed on, see
+    /// [`Energy::min_size`](crate::Energy::min_size)
+    ImageTooSmall {
+        width: usize,
+        height: usize,
+        kernel: usize,
+    },
+    /// The standard deviation of a blur is not positive, or above
+    /// [`Gaussian::MAX_SIGMA`]
+    InvalidSigma(f32),
+    /// A kernel is set but the energy is not built on one, see
+    /// [`Energy::set_kernel`](crate::Energy::set_kernel), or no energy is
+    /// computed at all
+    KernelNotUsed,
+    /// The target is larger than the image and enlargement is denied
+    EnlargementDenied {
+        from: (usize, usize),
+        to: (usize, usize),
+    },
+}
+
+impl fmt::Display for CarveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDimensions { expected, found } => write!(
+                f,
+                "expected dimensions {}x{}, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            Self::InvalidBufferLength { expected, found } => {
+                write!(f, "expected a buffer of {expected} values, found {found}")
+            }
+            Self::UnsupportedPixelType(color) => write!(f, "unsupported pixel type {color:?}"),
+            Self::ZeroSizeTarget => write!(f, "the target width and height must not be zero"),
+            Self::ImageTooSmall {
+                width,
+                height,
+                kernel,
+            } => write!(
+                f,
+                "a {width}x{height} image is too small for an energy needing {kernel}x{kernel} pixels"
+            ),
+            Self::InvalidSigma(sigma) => {
+                write!(
+                    f,
+                    "the blur sigma must be positive and at most {}, found {sigma}",
+                    Gaussian::MAX_SIGMA
+                )
+            }
+            Self::KernelNotUsed => write!(f, "the energy does not use a kernel"),
+            Self::EnlargementDenied { from, to } => write!(
+                f,
+                "cannot enlarge a {}x{} image to {}x{}",
+                from.0, from.1, to.0, to.1
+            ),
+        }
+    }
+}
+
+impl Error for CarveError {}
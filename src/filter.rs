@@ -0,0 +1,327 @@
+use std::{
+    borrow::Cow,
+    cmp::{max, min},
+    ops::Range,
+};
+
+use crate::{
+    energy::{update_from_energy, Energy},
+    error::CarveError,
+    sobel::Kernel,
+};
+
+/// A Gaussian blur, run on the image before the energy to keep noise such
+/// as JPEG artifacts out of it
+#[derive(Debug, Clone, Copy)]
+pub struct Gaussian {
+    sigma: f32,
+    size: Option<usize>,
+}
+
+impl Gaussian {
+    /// The largest sigma, which already blurs away anything but the coarsest
+    /// shapes
+    pub const MAX_SIGMA: f32 = 100.0;
+
+    /// Fails with [`CarveError::InvalidSigma`] unless `sigma` is positive
+    /// and at most [`Gaussian::MAX_SIGMA`]
+    pub fn new(sigma: f32) -> Result<Self, CarveError> {
+        if !(sigma > 0.0 && sigma <= Self::MAX_SIGMA) {
+            return Err(CarveError::InvalidSigma(sigma));
+        }
+        Ok(Self { sigma, size: None })
+    }
+
+    /// The side of the kernel, even sizes are rounded up. Defaults to three
+    /// sigmas on each side of the center.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn radius(&self) -> usize {
+        match self.size {
+            Some(size) => size / 2,
+            None => (3.0 * self.sigma).ceil() as usize,
+        }
+    }
+
+    /// The normalized weights up to `radius`, from the center outwards
+    fn weights(&self, radius: usize) -> Vec<f32> {
+        let weights: Vec<f32> = (0..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * self.sigma * self.sigma)).exp())
+            .collect();
+        let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        weights.iter().map(|w| w / sum).collect()
+    }
+
+    /// Blur the pixels in `cols` x `rows` of an image of interleaved
+    /// `channels`, replicating the border. The result holds the region
+    /// only, row by row. Every pixel is computed the same way whatever the
+    /// region, so blurring part of an image matches blurring all of it.
+    /// Taps past the larger side of the image only repeat the border, so
+    /// the kernel is cut there.
+    pub fn blur_region(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        channels: usize,
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) -> Vec<u8> {
+        if cols.is_empty() || rows.is_empty() {
+            return Vec::new();
+        }
+        let weights = self.weights(min(self.radius(), max(width, height)));
+        let b = weights.len() - 1;
+        let clamp = |idx: isize, len: usize| idx.clamp(0, len as isize - 1) as usize;
+        let tap = |weights: &[f32], i: usize| weights[i.abs_diff(b)];
+        // Vertical pass over the columns the horizontal pass reads
+        let cols_v = cols.start.saturating_sub(b)..min(cols.end + b, width);
+        let stride = cols_v.len() * channels;
+        let mut vertical = vec![0.0; stride * rows.len()];
+        for (r, line) in rows.clone().zip(vertical.chunks_mut(stride)) {
+            for i in 0..2 * b + 1 {
+                let w = tap(&weights, i);
+                let src = clamp(r as isize + i as isize - b as isize, height) * width;
+                let src = &image[(src + cols_v.start) * channels..(src + cols_v.end) * channels];
+                for (acc, &v) in line.iter_mut().zip(src) {
+                    *acc += w * v as f32;
+                }
+            }
+        }
+        let mut res = Vec::with_capacity(cols.len() * rows.len() * channels);
+        for line in vertical.chunks(stride) {
+            for c in cols.clone() {
+                for ch in 0..channels {
+                    let mut acc = 0.0;
+                    for i in 0..2 * b + 1 {
+                        let x = clamp(c as isize + i as isize - b as isize, width);
+                        acc += tap(&weights, i) * line[(x - cols_v.start) * channels + ch];
+                    }
+                    res.push(acc.round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Runs an energy on the image blurred by a [`Gaussian`]
+#[derive(Debug, Clone)]
+pub struct Blurred<E: Energy> {
+    energy: E,
+    gaussian: Gaussian,
+}
+
+impl<E: Energy> Blurred<E> {
+    pub fn new(energy: E, gaussian: Gaussian) -> Self {
+        Self { energy, gaussian }
+    }
+}
+
+impl<E: Energy> Energy for Blurred<E> {
+    fn energy(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        let channels = self.channels();
+        let blurred =
+            self.gaussian
+                .blur_region(image, width, height, channels, 0..width, 0..height);
+        self.energy.energy(&blurred, width, height)
+    }
+
+    fn channels(&self) -> usize {
+        self.energy.channels()
+    }
+
+    fn radius(&self) -> Option<usize> {
+        Some(self.energy.radius()? + self.gaussian.radius())
+    }
+
+    fn min_size(&self) -> usize {
+        self.energy.min_size()
+    }
+
+    fn set_kernel(&mut self, kernel: Kernel) -> bool {
+        self.energy.set_kernel(kernel)
+    }
+
+    /// The vertical pass always runs first, so the blurred pixels may round
+    /// differently once the image is transposed
+    fn orientation_invariant(&self) -> bool {
+        false
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        let Some(b) = self.energy.radius() else {
+            return update_from_energy(self, image, width, height, buf, cols, rows);
+        };
+        // Blur the pixels the energy reads, then update the crop. Its edges
+        // inside the image are out of reach of the updated pixels.
+        let crop_x = cols.start.saturating_sub(b)..min(cols.end + b, width);
+        let crop_y = rows.start.saturating_sub(b)..min(rows.end + b, height);
+        let (crop_w, crop_h) = (crop_x.len(), crop_y.len());
+        let channels = self.channels();
+        let blurred = self.gaussian.blur_region(
+            image,
+            width,
+            height,
+            channels,
+            crop_x.clone(),
+            crop_y.clone(),
+        );
+        let mut crop_buf = vec![0.0; crop_w * crop_h];
+        let crop_cols = cols.start - crop_x.start..cols.end - crop_x.start;
+        let crop_rows = rows.start - crop_y.start..rows.end - crop_y.start;
+        self.energy.update(
+            &blurred,
+            crop_w,
+            crop_h,
+            &mut crop_buf,
+            crop_cols.clone(),
+            crop_rows.clone(),
+        );
+        for (r, crop_r) in rows.zip(crop_rows) {
+            let line = r * width;
+            let crop_line = crop_r * crop_w;
+            buf[line + cols.start..line + cols.end]
+                .copy_from_slice(&crop_buf[crop_line + crop_cols.start..crop_line + crop_cols.end]);
+        }
+    }
+}
+
+/// How the energy is rescaled before looking for a seam. Masks and energy
+/// maps are added afterwards, so their weight does not depend on the scale
+/// of the energy. Forward energy ignores it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Normalize {
+    #[default]
+    None,
+    /// Map the lowest energy to 0 and the highest to 1
+    MinMax,
+    /// Clip the energy to the given quantile, e.g. 0.99, then divide by it.
+    /// Keeps a few strong edges from dwarfing all the others.
+    Percentile(f32),
+    /// `ln(1 + e)`, compressing strong edges
+    Log,
+}
+
+impl Normalize {
+    pub fn apply<'a>(&self, energy: &'a [f32]) -> Cow<'a, [f32]> {
+        let scale = |energy: &[f32], low: f32, high: f32| -> Vec<f32> {
+            let range = high - low;
+            energy
+                .iter()
+                .map(|&e| match range > 0.0 {
+                    true => (e.min(high) - low) / range,
+                    false => 0.0,
+                })
+                .collect()
+        };
+        match *self {
+            Self::None => Cow::Borrowed(energy),
+            Self::MinMax => {
+                let low = energy.iter().copied().fold(f32::INFINITY, f32::min);
+                let high = energy.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                Cow::Owned(scale(energy, low, high))
+            }
+            Self::Percentile(quantile) => {
+                let mut sorted = energy.to_vec();
+                let nth = (quantile.clamp(0.0, 1.0) * (energy.len() - 1) as f32).round();
+                let (_, &mut high, _) = sorted.select_nth_unstable_by(nth as usize, f32::total_cmp);
+                Cow::Owned(scale(energy, 0.0, high))
+            }
+            Self::Log => Cow::Owned(energy.iter().map(|e| e.max(0.0).ln_1p()).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{energy::L1Gradient, sobel::Sobel};
+    use rstest::rstest;
+
+    #[test]
+    fn test_gaussian_weights() {
+        let gaussian = Gaussian::new(1.0).unwrap();
+        assert_eq!(3, gaussian.radius());
+        assert_eq!(2, gaussian.size(5).radius());
+        let weights = gaussian.weights(gaussian.radius());
+        let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        assert!((sum - 1.0).abs() < 1e-6);
+        for sigma in [0.0, -1.0, 1e9, f32::NAN, f32::INFINITY] {
+            assert!(Gaussian::new(sigma).is_err());
+        }
+        assert!(weights.windows(2).all(|w| w[0] > w[1]));
+
+        // A flat image stays flat
+        let blurred = gaussian.blur_region(&[90; 35], 7, 5, 1, 0..7, 0..5);
+        assert_eq!(vec![90; 35], blurred);
+        assert!(gaussian
+            .blur_region(&[90; 35], 7, 5, 1, 3..3, 0..5)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_blur_huge_kernel() {
+        // The kernel is cut to the image instead of allocating it whole
+        let image: Vec<u8> = (0..3 * 35).map(|v| (v * 37 % 256) as u8).collect();
+        let gaussian = Gaussian::new(Gaussian::MAX_SIGMA).unwrap();
+        let cut = gaussian.size(15).blur_region(&image, 7, 5, 3, 0..7, 0..5);
+        assert_eq!(cut, gaussian.blur_region(&image, 7, 5, 3, 0..7, 0..5));
+        let huge = gaussian.size(usize::MAX);
+        assert_eq!(cut, huge.blur_region(&image, 7, 5, 3, 0..7, 0..5));
+    }
+
+    #[test]
+    fn test_blur_region() {
+        let image: Vec<u8> = (0..3 * 99).map(|v| (v * 37 % 256) as u8).collect();
+        let gaussian = Gaussian::new(1.5).unwrap().size(5);
+        let full = gaussian.blur_region(&image, 11, 9, 3, 0..11, 0..9);
+        let region = gaussian.blur_region(&image, 11, 9, 3, 3..8, 1..4);
+        for (r, line) in (1..4).zip(region.chunks(5 * 3)) {
+            assert_eq!(&full[(r * 11 + 3) * 3..(r * 11 + 8) * 3], line);
+        }
+    }
+
+    #[rstest]
+    fn test_blurred_update(
+        #[values(
+            &Blurred::new(Sobel::new(), Gaussian::new(1.0).unwrap()) as &dyn Energy,
+            &Blurred::new(L1Gradient, Gaussian::new(0.8).unwrap().size(3)) as &dyn Energy
+        )]
+        energy: &dyn Energy,
+    ) {
+        let image: Vec<u8> = (0..20 * 15).map(|v| (v * 37 % 256) as u8).collect();
+        let expected = energy.energy(&image, 20, 15);
+        let mut buf = expected.clone();
+        buf[20 * 4..20 * 9].fill(-1.0);
+        energy.update(&image, 20, 15, &mut buf, 0..20, 4..9);
+        assert_eq!(expected, buf);
+        buf[..].fill(-1.0);
+        energy.update(&image, 20, 15, &mut buf, 0..20, 0..15);
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let energy = [2.0, 4.0, 6.0, 10.0, 100.0];
+        assert_eq!(&energy, &*Normalize::None.apply(&energy));
+        let min_max = Normalize::MinMax.apply(&energy);
+        assert_eq!(&[0.0, 2.0 / 98.0, 4.0 / 98.0, 8.0 / 98.0, 1.0], &*min_max);
+        let clipped = Normalize::Percentile(0.75).apply(&energy);
+        assert_eq!(&[0.2, 0.4, 0.6, 1.0, 1.0], &*clipped);
+        let log = Normalize::Log.apply(&energy);
+        assert_eq!(101f32.ln(), log[4]);
+        assert_eq!(&[0.0; 3], &*Normalize::MinMax.apply(&[5.0; 3]));
+    }
+}
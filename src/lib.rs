@@ -1,5 +1,12 @@
 pub mod cost;
+pub mod energy;
+pub mod error;
+pub mod filter;
+pub mod mask;
 pub mod seam;
 pub mod sobel;
 
+pub use energy::Energy;
+pub use error::CarveError;
+pub use mask::{EnergyMap, Mask};
 pub use seam::SeamCarver;
@@ -0,0 +1,155 @@
+use image::{DynamicImage, GrayImage};
+
+use crate::CarveError;
+
+/// A per-pixel selection of an image, stored row by row
+#[derive(Debug, Clone)]
+pub struct Mask {
+    width: usize,
+    height: usize,
+    data: Vec<bool>,
+}
+
+impl Mask {
+    pub fn new(width: usize, height: usize, data: Vec<bool>) -> Result<Self, CarveError> {
+        if data.len() != width * height {
+            return Err(CarveError::InvalidBufferLength {
+                expected: width * height,
+                found: data.len(),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn as_slice(&self) -> &[bool] {
+        &self.data
+    }
+}
+
+/// Pixels brighter than mid-gray are selected
+impl From<&GrayImage> for Mask {
+    fn from(img: &GrayImage) -> Self {
+        Self {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            data: img.as_raw().iter().map(|&v| v >= 128).collect(),
+        }
+    }
+}
+
+impl From<GrayImage> for Mask {
+    fn from(img: GrayImage) -> Self {
+        Self::from(&img)
+    }
+}
+
+/// A per-pixel importance of an image, such as a depth map or a
+/// segmentation, stored row by row
+#[derive(Debug, Clone)]
+pub struct EnergyMap {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl EnergyMap {
+    pub fn new(width: usize, height: usize, data: Vec<f32>) -> Result<Self, CarveError> {
+        if data.len() != width * height {
+            return Err(CarveError::InvalidBufferLength {
+                expected: width * height,
+                found: data.len(),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+}
+
+/// The luma of the image, from 0 for black to 1 for white whatever the
+/// pixel type. HDR images may go above 1.
+impl From<&DynamicImage> for EnergyMap {
+    fn from(img: &DynamicImage) -> Self {
+        Self {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            data: img.to_luma32f().into_raw(),
+        }
+    }
+}
+
+impl From<DynamicImage> for EnergyMap {
+    fn from(img: DynamicImage) -> Self {
+        Self::from(&img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_image() {
+        let img = GrayImage::from_raw(3, 2, vec![0, 127, 128, 255, 10, 200]).unwrap();
+        let mask = Mask::from(&img);
+        assert_eq!(3, mask.width());
+        assert_eq!(2, mask.height());
+        assert_eq!(&[false, false, true, true, false, true], mask.as_slice());
+    }
+
+    #[test]
+    fn test_new_checks_length() {
+        assert!(Mask::new(3, 2, vec![false; 6]).is_ok());
+        let err = Mask::new(3, 2, vec![false; 5]).unwrap_err();
+        let expected = CarveError::InvalidBufferLength {
+            expected: 6,
+            found: 5,
+        };
+        assert_eq!(expected, err);
+
+        let err = EnergyMap::new(3, 2, vec![0.0; 7]).unwrap_err();
+        let expected = CarveError::InvalidBufferLength {
+            expected: 6,
+            found: 7,
+        };
+        assert_eq!(expected, err);
+    }
+
+    #[test]
+    fn test_energy_map_from_image() {
+        let img = GrayImage::from_raw(2, 1, vec![0, 255]).unwrap();
+        let map = EnergyMap::from(DynamicImage::from(img));
+        assert_eq!(&[0.0, 1.0], map.as_slice());
+
+        let img = image::ImageBuffer::from_raw(2, 1, vec![0u16, 65535]).unwrap();
+        let map = EnergyMap::from(DynamicImage::ImageLuma16(img));
+        assert_eq!((2, 1), (map.width(), map.height()));
+        assert_eq!(&[0.0, 1.0], map.as_slice());
+    }
+}
@@ -1,12 +1,25 @@
 use crate::{
-    cost::{build_cost_matrix, find_shortest_path, Direction},
+    cost::{
+        build_cost_matrix, build_forward_cost_matrix, find_forward_shortest_path,
+        find_shortest_path, transpose, Direction, EnergyMode,
+    },
+    energy::Energy,
+    error::CarveError,
+    filter::{Blurred, Gaussian, Normalize},
+    mask::{EnergyMap, Mask},
     sobel::{Kernel, Sobel},
 };
-use image::{DynamicImage, GrayImage, RgbImage};
+use image::{ColorType, DynamicImage, ImageBuffer};
+use std::{
+    borrow::Cow,
+    cmp::{max, min},
+    mem,
+    ops::Range,
+};
 
 fn remove_path_from_image<I>(
     img: &mut Vec<I>,
-    path: Vec<usize>,
+    path: &[usize],
     no_channels: usize,
     dir: Direction,
     width: usize,
@@ -21,7 +34,7 @@ fn remove_path_from_image<I>(
 
 fn remove_path_from_image_dir_col<I>(
     img: &mut Vec<I>,
-    path: Vec<usize>,
+    path: &[usize],
     no_channels: usize,
     width: usize,
 ) where
@@ -42,18 +55,16 @@ fn remove_path_from_image_dir_col<I>(
     img.resize(new_len, Default::default());
 }
 
-fn remove_path_from_image_dir_row<I>(img: &mut Vec<I>, mut path: Vec<usize>, no_channels: usize)
+/// `path` holds one pixel per row, so its indices are increasing
+fn remove_path_from_image_dir_row<I>(img: &mut Vec<I>, path: &[usize], no_channels: usize)
 where
     I: Copy + Default,
 {
-    // We want the lowest index as the first item
-    path.sort();
-    path.reverse();
-
+    debug_assert!(path.is_sorted());
     let new_len = img.len() - path.len() * no_channels;
-    let mut idx = path.pop().unwrap() * no_channels;
+    let mut idx = path[0] * no_channels;
     let mut inc = no_channels;
-    while let Some(next_idx) = path.pop().map(|i| i * no_channels) {
+    for next_idx in path[1..].iter().map(|i| i * no_channels) {
         for i in idx..(next_idx - inc) {
             img[i] = img[i + inc];
         }
@@ -66,8 +77,152 @@ where
 
     img.resize(new_len, Default::default());
 }
-#[derive(Debug, Clone, Copy)]
+/// Pixel values that can be blended when a seam is duplicated
+trait Pixel: Copy + Default {
+    fn average(a: Self, b: Self) -> Self;
+}
+
+impl Pixel for u8 {
+    #[inline]
+    fn average(a: Self, b: Self) -> Self {
+        (a as u16 + b as u16).div_ceil(2) as u8
+    }
+}
+
+impl Pixel for u16 {
+    #[inline]
+    fn average(a: Self, b: Self) -> Self {
+        (a as u32 + b as u32).div_ceil(2) as u16
+    }
+}
+
+impl Pixel for f32 {
+    #[inline]
+    fn average(a: Self, b: Self) -> Self {
+        (a + b) / 2.0
+    }
+}
+
+/// Duplicate every marked pixel, the new pixel being the average of the
+/// marked pixel and its successor along the seam's inner direction.
+/// `marks` holds one entry per pixel and every line must contain the same
+/// number of marked pixels.
+fn insert_paths_into_image<I>(
+    img: &[I],
+    marks: &[bool],
+    no_channels: usize,
+    dir: Direction,
+    width: usize,
+    height: usize,
+) -> Vec<I>
+where
+    I: Pixel,
+{
+    insert_paths_with(img, marks, no_channels, dir, width, height, I::average)
+}
+
+/// Duplicate every marked pixel as is, for masks whose values must not be
+/// blended with their neighbours'
+fn duplicate_paths_in_image<I>(
+    img: &[I],
+    marks: &[bool],
+    no_channels: usize,
+    dir: Direction,
+    width: usize,
+    height: usize,
+) -> Vec<I>
+where
+    I: Copy + Default,
+{
+    insert_paths_with(img, marks, no_channels, dir, width, height, |a, _| a)
+}
 
+/// Insert after every marked pixel the value `blend` makes of the marked
+/// pixel and its successor
+fn insert_paths_with<I>(
+    img: &[I],
+    marks: &[bool],
+    no_channels: usize,
+    dir: Direction,
+    width: usize,
+    height: usize,
+    blend: fn(I, I) -> I,
+) -> Vec<I>
+where
+    I: Copy + Default,
+{
+    match dir {
+        Direction::Row => {
+            insert_paths_into_image_dir_row(img, marks, no_channels, width, height, blend)
+        }
+        Direction::Column => {
+            insert_paths_into_image_dir_col(img, marks, no_channels, width, height, blend)
+        }
+    }
+}
+
+fn insert_paths_into_image_dir_row<I>(
+    img: &[I],
+    marks: &[bool],
+    no_channels: usize,
+    width: usize,
+    height: usize,
+    blend: fn(I, I) -> I,
+) -> Vec<I>
+where
+    I: Copy + Default,
+{
+    let k = marks.iter().filter(|&&m| m).count() / height;
+    let mut res = Vec::with_capacity((width + k) * height * no_channels);
+    for r in 0..height {
+        for c in 0..width {
+            let idx = r * width + c;
+            let px = idx * no_channels;
+            res.extend_from_slice(&img[px..px + no_channels]);
+            if marks[idx] {
+                let next = if c + 1 < width { px + no_channels } else { px };
+                for i in 0..no_channels {
+                    res.push(blend(img[px + i], img[next + i]));
+                }
+            }
+        }
+    }
+    res
+}
+
+fn insert_paths_into_image_dir_col<I>(
+    img: &[I],
+    marks: &[bool],
+    no_channels: usize,
+    width: usize,
+    height: usize,
+    blend: fn(I, I) -> I,
+) -> Vec<I>
+where
+    I: Copy + Default,
+{
+    let k = marks.iter().filter(|&&m| m).count() / width;
+    let line = width * no_channels;
+    let mut res = vec![I::default(); (height + k) * line];
+    for c in 0..width {
+        let mut out = c * no_channels;
+        for r in 0..height {
+            let px = (r * width + c) * no_channels;
+            res[out..out + no_channels].copy_from_slice(&img[px..px + no_channels]);
+            out += line;
+            if marks[r * width + c] {
+                let next = if r + 1 < height { px + line } else { px };
+                for i in 0..no_channels {
+                    res[out + i] = blend(img[px + i], img[next + i]);
+                }
+                out += line;
+            }
+        }
+    }
+    res
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Dims {
     width: usize,
     height: usize,
@@ -79,81 +234,1047 @@ impl Dims {
     }
 }
 
-pub struct SeamCarver {
-    orig: Dims,
-    desired: Dims,
-    img: Option<DynamicImage>,
+/// How the energy map is kept up to date as seams are removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Recompute {
+    /// Carve the energy map along with the image
+    Never,
+    /// Recompute the energy around the removed seam only
+    #[default]
+    Local,
+    /// Recompute the whole energy map after every seam
+    Full,
+}
+
+/// The value of `mask_buf` on the removal mask
+const REMOVE: i8 = -1;
+
+/// The value of `mask_buf` on the protection mask
+const PROTECT: i8 = 1;
+
+/// The order in which horizontal and vertical seams are removed when both
+/// dimensions shrink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeamOrder {
+    /// Remove all the horizontal seams, then all the vertical ones
+    #[default]
+    ColumnsThenRows,
+    /// Remove whichever of the best horizontal and vertical seams is
+    /// cheaper at each step
+    Greedy,
+    /// Pick the interleaving with the lowest total energy using the
+    /// transport map of the paper. Keeps a copy of the image for every
+    /// vertical seam to remove, so it is slow and memory hungry.
+    Optimal,
+}
+
+/// What to do when the target is larger than the image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Enlarge {
+    /// Insert seams, at most half the image at a time
+    #[default]
+    Insert,
+    /// Fail with [`CarveError::EnlargementDenied`]
+    Deny,
+}
+
+/// How the buffers are walked when carving horizontal seams
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Walk down the columns, striding over whole rows
+    #[default]
+    Strided,
+    /// Transpose the image and its buffers before a run of horizontal
+    /// seams and back afterwards, so seams are always found along rows.
+    /// Pays off on large images once more than a few seams are carved, see
+    /// `benches/carve.rs`. Only used with energies that do not depend on
+    /// the orientation of the image, see [`Energy::orientation_invariant`],
+    /// so the result is the same as [`Layout::Strided`]'s. Of the built-in
+    /// energies, that is Sobel and the gradients of the `energy` module.
+    /// [`Hog`](crate::sobel::Hog) bins would split differently, and
+    /// [`Entropy`](crate::energy::Entropy),
+    /// [`Saliency`](crate::energy::Saliency) and any energy run on a
+    /// [`SeamCarver::blur`] would sum in another order.
+    Transposed,
+}
+
+/// How an energy map is combined with the computed energy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapBlend {
+    /// Use the map alone
+    Replace,
+    /// Add the map times the weight to the computed energy
+    Add(f32),
+}
+
+/// No energy at all, for energy maps replacing it and for forward energy,
+/// which does not read it
+struct Flat;
+
+impl Energy for Flat {
+    fn energy(&self, _image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        vec![0.0; width * height]
+    }
+
+    fn orientation_invariant(&self) -> bool {
+        true
+    }
+
+    fn radius(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    /// Carving zeros leaves zeros
+    fn update(
+        &self,
+        _image: &[u8],
+        _width: usize,
+        _height: usize,
+        _buf: &mut [f32],
+        _cols: Range<usize>,
+        _rows: Range<usize>,
+    ) {
+    }
+}
+
+/// Called with the number of seams removed or inserted so far and the
+/// total number of seams to reach the target size
+pub type Progress = Box<dyn FnMut(usize, usize)>;
+
+/// The image being carved and the buffers carved in step with it
+#[derive(Clone)]
+struct CarveState {
+    /// The samples of the image, only turned back into an image once
+    /// carved
+    pixels: PixelBuf,
+    color: ColorType,
+    dims: Dims,
     gray_buf: Vec<u8>,
+    /// RGB copy of the image, kept for energies reading color only
+    rgb_buf: Option<Vec<u8>>,
     energy_buf: Vec<f32>,
+    /// [`REMOVE`] or [`PROTECT`] on masked pixels, 0 elsewhere
+    mask_buf: Option<Vec<i8>>,
+    /// The energy map times its weight, added on top of `energy_buf`
+    map_buf: Option<Vec<f32>>,
+}
+
+/// An empty state, left behind when the state is moved out
+impl Default for CarveState {
+    fn default() -> Self {
+        Self {
+            pixels: PixelBuf::U8(Vec::new()),
+            color: ColorType::L8,
+            dims: Dims::new(0, 0),
+            gray_buf: Vec::new(),
+            rgb_buf: None,
+            energy_buf: Vec::new(),
+            mask_buf: None,
+            map_buf: None,
+        }
+    }
+}
+
+impl CarveState {
+    /// Swap the rows and columns of the image and of every buffer
+    fn transpose(&mut self) {
+        let Dims { width, height } = self.dims;
+        let no_channels = self.color.channel_count() as usize;
+        self.pixels = self.pixels.transpose(no_channels, width, height);
+        self.dims = Dims::new(height, width);
+        self.gray_buf = transpose(&self.gray_buf, 1, width, height);
+        self.energy_buf = transpose(&self.energy_buf, 1, width, height);
+        if let Some(rgb) = self.rgb_buf.as_mut() {
+            *rgb = transpose(rgb, 3, width, height);
+        }
+        if let Some(mask) = self.mask_buf.as_mut() {
+            *mask = transpose(mask, 1, width, height);
+        }
+        if let Some(map) = self.map_buf.as_mut() {
+            *map = transpose(map, 1, width, height);
+        }
+    }
+}
+
+pub struct SeamCarver {
+    desired: Dims,
+    state: CarveState,
+    energy_mode: EnergyMode,
+    order: SeamOrder,
+    energy: Box<dyn Energy>,
+    /// Set on `energy` once it is final
+    kernel: Option<Kernel>,
+    recompute: Recompute,
+    enlarge: Enlarge,
+    layout: Layout,
+    progress: Option<Progress>,
+    /// Wrapped around `energy` once it is final
+    blur: Option<Gaussian>,
+    normalize: Normalize,
+    /// Turned into `map_buf` once the energy is known
+    energy_map: Option<(EnergyMap, MapBlend)>,
+    /// Seams carved so far and in total, as passed to `progress`
+    done: usize,
+    total: usize,
 }
 
 impl SeamCarver {
-    pub fn new(img: DynamicImage, new_width: usize, new_height: usize) -> Self {
-        let width = img.width() as usize;
-        let height = img.height() as usize;
-        if new_height > height || new_width > width {
-            panic!("Can only reduce img in size");
+    /// Targets larger than the source are reached by seam insertion.
+    pub fn new(img: DynamicImage, new_width: usize, new_height: usize) -> Result<Self, CarveError> {
+        if new_width == 0 || new_height == 0 {
+            return Err(CarveError::ZeroSizeTarget);
+        }
+        let color = img.color();
+        if !is_supported(color) {
+            return Err(CarveError::UnsupportedPixelType(color));
         }
-        let orig = Dims::new(width, height);
         let desired = Dims::new(new_width, new_height);
-
-        let gray_buf = img.grayscale().into_luma8().into_vec();
-        let sobel = Sobel::new().kernel(Kernel::X3);
-        let energy_buf = sobel.apply(&gray_buf, width, height);
-        Self {
-            orig,
+        let dims = Dims::new(img.width() as usize, img.height() as usize);
+        let (pixels, color) = into_buffer(img);
+        let state = CarveState {
+            pixels,
+            color,
+            dims,
+            ..Default::default()
+        };
+        Ok(Self {
             desired,
-            img: Some(img),
-            gray_buf,
-            energy_buf,
+            state,
+            energy_mode: EnergyMode::default(),
+            order: SeamOrder::default(),
+            energy: Box::new(Sobel::new().kernel(Kernel::X3)),
+            kernel: None,
+            recompute: Recompute::default(),
+            enlarge: Enlarge::default(),
+            layout: Layout::default(),
+            progress: None,
+            blur: None,
+            normalize: Normalize::default(),
+            energy_map: None,
+            done: 0,
+            total: 0,
+        })
+    }
+
+    /// [`EnergyMode::Forward`] computes its costs from the grayscale image
+    /// alone, so the energy function, blur and normalization are not used
+    /// and the energy is never computed, and setting a kernel fails with
+    /// [`CarveError::KernelNotUsed`]. Masks and energy maps are
+    /// added to the forward costs, an energy map set with
+    /// [`MapBlend::Replace`] included.
+    pub fn energy_mode(mut self, energy_mode: EnergyMode) -> Self {
+        self.energy_mode = energy_mode;
+        self
+    }
+
+    /// The kernel of the energy, whichever order it is set in, the default
+    /// is [`Kernel::X3`]. Applies to the energies built on one, see
+    /// [`Energy::set_kernel`]. Others fail with
+    /// [`CarveError::KernelNotUsed`], and so do forward energy and an energy
+    /// map set with [`MapBlend::Replace`], which compute no energy.
+    pub fn kernel(mut self, kernel: Kernel) -> Self {
+        self.kernel = Some(kernel);
+        self
+    }
+
+    /// Use `energy` instead of the 3x3 Sobel operator. Backward energy only.
+    pub fn energy(mut self, energy: impl Energy + 'static) -> Self {
+        self.energy = Box::new(energy);
+        self
+    }
+
+    pub fn order(mut self, order: SeamOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn recompute(mut self, recompute: Recompute) -> Self {
+        self.recompute = recompute;
+        self
+    }
+
+    pub fn enlarge(mut self, enlarge: Enlarge) -> Self {
+        self.enlarge = enlarge;
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Blur the image before computing its energy, to keep noise such as
+    /// JPEG artifacts from pulling seams through the subject. Backward
+    /// energy only.
+    pub fn blur(mut self, gaussian: Gaussian) -> Self {
+        self.blur = Some(gaussian);
+        self
+    }
+
+    /// Rescale the energy before each seam search, see [`Normalize`]
+    pub fn normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Call `progress` after every seam removed and every batch of seams
+    /// inserted. Seams removed by the removal mask are not counted, and the
+    /// optimal order reports once per row of its transport map.
+    pub fn progress(mut self, progress: impl FnMut(usize, usize) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Remove the selected pixels before retargeting. A seam crossing more
+    /// selected pixels is always cheaper whatever the energy, and seams
+    /// are removed until no
+    /// selected pixel is left, then the image is carved or enlarged
+    /// to the requested size, so asking for the original size fills the
+    /// hole back in.
+    pub fn remove_mask(mut self, mask: impl Into<Mask>) -> Result<Self, CarveError> {
+        self.set_mask(&mask.into(), REMOVE)?;
+        Ok(self)
+    }
+
+    /// Keep seams away from the selected pixels, e.g. faces, logos or text.
+    /// A seam crossing more protected pixels always costs more whatever the
+    /// energy, so seams only go through the mask where every seam has to.
+    /// Where it overlaps the removal mask, the mask set last wins.
+    pub fn protect_mask(mut self, mask: impl Into<Mask>) -> Result<Self, CarveError> {
+        self.set_mask(&mask.into(), PROTECT)?;
+        Ok(self)
+    }
+
+    /// Combine a per-pixel importance, such as a depth map or a
+    /// segmentation, with the energy. The map is carved along with the
+    /// image, and however large it cannot outweigh the masks. Forward
+    /// energy always adds the map, see [`SeamCarver::energy_mode`].
+    pub fn energy_map(
+        mut self,
+        map: impl Into<EnergyMap>,
+        blend: MapBlend,
+    ) -> Result<Self, CarveError> {
+        let map = map.into();
+        self.check_dims(map.width(), map.height())?;
+        self.energy_map = Some((map, blend));
+        Ok(self)
+    }
+
+    fn check_dims(&self, width: usize, height: usize) -> Result<(), CarveError> {
+        let dims = self.dims();
+        if width != dims.width || height != dims.height {
+            return Err(CarveError::InvalidDimensions {
+                expected: (dims.width, dims.height),
+                found: (width, height),
+            });
         }
+        Ok(())
     }
 
-    pub fn apply(mut self) -> DynamicImage {
-        let w_diff = self.orig.width - self.desired.width;
-        let h_diff = self.orig.height - self.desired.height;
-        for _ in 0..h_diff {
-            self.remove_seam(Direction::Column);
+    fn set_mask(&mut self, mask: &Mask, value: i8) -> Result<(), CarveError> {
+        self.check_dims(mask.width(), mask.height())?;
+        let Dims { width, height } = self.dims();
+        let len = width * height;
+        let buf = self.state.mask_buf.get_or_insert_with(|| vec![0; len]);
+        for (val, _) in buf.iter_mut().zip(mask.as_slice()).filter(|(_, &m)| m) {
+            *val = value;
         }
-        for _ in 0..w_diff {
-            self.remove_seam(Direction::Row);
+        Ok(())
+    }
+
+    pub fn apply(mut self) -> Result<DynamicImage, CarveError> {
+        self.prepare()?;
+        self.remove_object();
+        let dims = self.dims();
+        if self.enlarge == Enlarge::Deny
+            && (self.desired.width > dims.width || self.desired.height > dims.height)
+        {
+            return Err(CarveError::EnlargementDenied {
+                from: (dims.width, dims.height),
+                to: (self.desired.width, self.desired.height),
+            });
         }
-        self.img.unwrap()
+        self.total =
+            dims.width.abs_diff(self.desired.width) + dims.height.abs_diff(self.desired.height);
+        let rows = dims.height.saturating_sub(self.desired.height);
+        let cols = dims.width.saturating_sub(self.desired.width);
+        match self.order {
+            SeamOrder::ColumnsThenRows => {}
+            SeamOrder::Greedy => {
+                self.carve_greedy(rows, cols);
+            }
+            SeamOrder::Optimal => {
+                self.carve_optimal(rows, cols);
+            }
+        }
+        let dims = self.dims();
+        self.retarget(dims.height, self.desired.height, Direction::Column);
+        self.retarget(dims.width, self.desired.width, Direction::Row);
+        let Dims { width, height } = self.dims();
+        Ok(from_buffer(
+            self.state.pixels,
+            self.state.color,
+            width,
+            height,
+        ))
     }
 
-    fn remove_seam(&mut self, dir: Direction) {
-        let img = self.img.take().unwrap();
-        let width = img.width() as usize;
-        let height = img.height() as usize;
-        let cost_mat = build_cost_matrix(&self.energy_buf, width, height, dir);
-        let path = find_shortest_path(&cost_mat, width, height, dir);
-        let (mut buf, no_channels) = match img {
-            DynamicImage::ImageLuma8(img) => (img.into_vec(), 1),
-            DynamicImage::ImageRgb8(img) => (img.into_vec(), 3),
-            _ => panic!("unsupported image format"),
+    /// Compute the buffers carved along with the image
+    fn prepare(&mut self) -> Result<(), CarveError> {
+        let replaced = matches!(self.energy_map, Some((_, MapBlend::Replace)));
+        if replaced || self.energy_mode == EnergyMode::Forward {
+            if self.kernel.is_some() {
+                return Err(CarveError::KernelNotUsed);
+            }
+            self.energy = Box::new(Flat);
+        } else {
+            if let Some(kernel) = self.kernel {
+                if !self.energy.set_kernel(kernel) {
+                    return Err(CarveError::KernelNotUsed);
+                }
+            }
+            if let Some(gaussian) = self.blur {
+                let energy = mem::replace(&mut self.energy, Box::new(Flat));
+                self.energy = Box::new(Blurred::new(energy, gaussian));
+            }
+        }
+        let Dims { width, height } = self.dims();
+        let kernel = self.energy.min_size();
+        if width < kernel || height < kernel {
+            return Err(CarveError::ImageTooSmall {
+                width,
+                height,
+                kernel,
+            });
+        }
+        // Borrow the samples as an image for the conversions
+        let pixels = mem::replace(&mut self.state.pixels, PixelBuf::U8(Vec::new()));
+        let img = from_buffer(pixels, self.state.color, width, height);
+        self.state.gray_buf = img.grayscale().into_luma8().into_vec();
+        if self.energy.channels() == 3 {
+            self.state.rgb_buf = Some(img.to_rgb8().into_vec());
+        }
+        self.state.pixels = into_buffer(img).0;
+        let input = self
+            .state
+            .rgb_buf
+            .as_deref()
+            .unwrap_or(&self.state.gray_buf);
+        self.state.energy_buf = self.energy.energy(input, width, height);
+        if let Some((map, blend)) = self.energy_map.take() {
+            let weight = match blend {
+                MapBlend::Replace => 1.0,
+                MapBlend::Add(weight) => weight,
+            };
+            self.state.map_buf = Some(map.as_slice().iter().map(|m| weight * m).collect());
+        }
+        Ok(())
+    }
+
+    fn dims(&self) -> Dims {
+        self.state.dims
+    }
+
+    /// Count `seams` more seams as done and report it
+    fn report(&mut self, seams: usize) {
+        self.done += seams;
+        if let Some(progress) = self.progress.as_mut() {
+            progress(self.done, self.total);
+        }
+    }
+
+    fn has_object(&self) -> bool {
+        self.state
+            .mask_buf
+            .as_ref()
+            .is_some_and(|buf| buf.contains(&REMOVE))
+    }
+
+    /// Remove seams until no pixel of the removal mask is left, along the
+    /// direction that crosses the fewest masked columns or rows.
+    fn remove_object(&mut self) {
+        if !self.has_object() {
+            return;
+        }
+        let Dims { width, height } = self.dims();
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (width, 0, height, 0);
+        let buf = self.state.mask_buf.as_ref().unwrap();
+        for (idx, _) in buf.iter().enumerate().filter(|(_, &v)| v == REMOVE) {
+            min_x = min(min_x, idx % width);
+            max_x = max(max_x, idx % width);
+            min_y = min(min_y, idx / width);
+            max_y = max(max_y, idx / width);
+        }
+        let dir = if max_x - min_x <= max_y - min_y {
+            Direction::Row
+        } else {
+            Direction::Column
         };
-        remove_path_from_image(&mut self.gray_buf, path.clone(), 1, dir, width);
-        remove_path_from_image(&mut self.energy_buf, path.clone(), 1, dir, width);
-        remove_path_from_image(&mut buf, path, no_channels, dir, width);
+        while self.has_object() {
+            let dims = self.dims();
+            let inner = match dir {
+                Direction::Row => dims.width,
+                Direction::Column => dims.height,
+            };
+            if inner <= 1 {
+                break;
+            }
+            self.remove_seam(dir);
+        }
+    }
+
+    fn retarget(&mut self, from: usize, to: usize, dir: Direction) {
+        if from != to
+            && self.layout == Layout::Transposed
+            && self.energy.orientation_invariant()
+            && matches!(dir, Direction::Column)
+        {
+            self.state.transpose();
+            self.retarget(from, to, Direction::Row);
+            self.state.transpose();
+            return;
+        }
+        if to <= from {
+            for _ in 0..(from - to) {
+                self.remove_seam(dir);
+                self.report(1);
+            }
+            return;
+        }
+        // Inserting more seams than half the image would duplicate the same
+        // low energy regions over and over, so enlarge in several steps.
+        let mut remaining = to - from;
+        while remaining > 0 {
+            let inner = match dir {
+                Direction::Row => self.dims().width,
+                Direction::Column => self.dims().height,
+            };
+            let k = remaining.min(max(inner / 2, 1));
+            self.insert_seams(k, dir);
+            self.report(k);
+            remaining -= k;
+        }
+    }
+
+    /// Remove `rows` horizontal and `cols` vertical seams, picking the
+    /// cheaper direction at each step. Returns the total seam energy.
+    fn carve_greedy(&mut self, mut rows: usize, mut cols: usize) -> f32 {
+        let mut total = 0.0;
+        while rows > 0 && cols > 0 {
+            let (row_path, row_cost) = self.best_seam(Direction::Column);
+            let (col_path, col_cost) = self.best_seam(Direction::Row);
+            if row_cost <= col_cost {
+                self.remove_path(&row_path, Direction::Column);
+                total += row_cost;
+                rows -= 1;
+            } else {
+                self.remove_path(&col_path, Direction::Row);
+                total += col_cost;
+                cols -= 1;
+            }
+            self.report(1);
+        }
+        total
+    }
+
+    /// Remove `rows` horizontal and `cols` vertical seams in the order that
+    /// minimizes the total seam energy. Every cell (i, j) of the transport
+    /// map holds the cheapest state reached by removing i horizontal and j
+    /// vertical seams, built from cell (i - 1, j) or (i, j - 1). Returns
+    /// the total seam energy.
+    fn carve_optimal(&mut self, rows: usize, cols: usize) -> f32 {
+        if rows == 0 || cols == 0 {
+            return 0.0;
+        }
+        let mut prev = Vec::with_capacity(cols + 1);
+        let mut total = 0.0;
+        prev.push((total, self.state.clone()));
+        for _ in 0..cols {
+            total += self.remove_seam(Direction::Row);
+            prev.push((total, self.state.clone()));
+        }
+
+        for _ in 0..rows {
+            let mut cur: Vec<(f32, CarveState)> = Vec::with_capacity(cols + 1);
+            for (j, (up_total, up_state)) in prev.into_iter().enumerate() {
+                self.state = up_state;
+                let (up_path, up_cost) = self.best_seam(Direction::Column);
+                let up_total = up_total + up_cost;
+                if j > 0 {
+                    let (left_total, left_state) = &cur[j - 1];
+                    let up_state = std::mem::replace(&mut self.state, left_state.clone());
+                    let (left_path, left_cost) = self.best_seam(Direction::Row);
+                    let left_total = left_total + left_cost;
+                    if left_total < up_total {
+                        self.remove_path(&left_path, Direction::Row);
+                        let state = std::mem::replace(&mut self.state, up_state);
+                        cur.push((left_total, state));
+                        continue;
+                    }
+                    self.state = up_state;
+                }
+                self.remove_path(&up_path, Direction::Column);
+                cur.push((up_total, std::mem::take(&mut self.state)));
+            }
+            prev = cur;
+            // Every row of the map is one more horizontal seam on the path,
+            // the vertical ones are only known once the map is complete.
+            self.report(1);
+        }
+        let (total, state) = prev.pop().unwrap();
+        self.state = state;
+        self.report(cols);
+        total
+    }
+
+    /// Remove the best seam along `dir` and return its cost
+    fn remove_seam(&mut self, dir: Direction) -> f32 {
+        let (path, cost) = self.best_seam(dir);
+        self.remove_path(&path, dir);
+        cost
+    }
+
+    fn best_seam(&self, dir: Direction) -> (Vec<usize>, f32) {
+        let Dims { width, height } = self.dims();
+        find_seam(
+            self.energy_mode,
+            &self.normalized(&self.state.energy_buf),
+            self.state.map_buf.as_deref(),
+            self.state.mask_buf.as_deref(),
+            &self.state.gray_buf,
+            width,
+            height,
+            dir,
+        )
+    }
+
+    /// The energy seams are looked for in, forward energy ignores it
+    fn normalized<'a>(&self, energy: &'a [f32]) -> Cow<'a, [f32]> {
+        match self.energy_mode {
+            EnergyMode::Backward => self.normalize.apply(energy),
+            EnergyMode::Forward => Cow::Borrowed(energy),
+        }
+    }
+
+    fn remove_path(&mut self, path: &[usize], dir: Direction) {
+        let Dims { width, height } = self.dims();
+        let state = &mut self.state;
+        let no_channels = state.color.channel_count() as usize;
+        state.pixels.remove_path(path, no_channels, dir, width);
+        if let Some(mask) = state.mask_buf.as_mut() {
+            remove_path_from_image(mask, path, 1, dir, width);
+        }
+        if let Some(map) = state.map_buf.as_mut() {
+            remove_path_from_image(map, path, 1, dir, width);
+        }
+        if let Some(rgb) = state.rgb_buf.as_mut() {
+            remove_path_from_image(rgb, path, 3, dir, width);
+        }
+        remove_path_from_image(&mut state.gray_buf, path, 1, dir, width);
+        remove_path_from_image(&mut state.energy_buf, path, 1, dir, width);
+        recompute_energy(
+            self.energy.as_ref(),
+            self.recompute,
+            state.rgb_buf.as_deref().unwrap_or(&state.gray_buf),
+            &mut state.energy_buf,
+            path,
+            dir,
+            width,
+            height,
+        );
+        state.dims = match dir {
+            Direction::Row => Dims::new(width - 1, height),
+            Direction::Column => Dims::new(width, height - 1),
+        };
+    }
+
+    /// Find the `k` lowest cost seams by removing them one after the other
+    /// from a copy of the energy map, and mark them in original coordinates.
+    fn find_seams(&self, k: usize, dir: Direction, width: usize, height: usize) -> Vec<bool> {
+        let mut energy = self.state.energy_buf.clone();
+        let mut gray = self.state.gray_buf.clone();
+        let mut rgb = self.state.rgb_buf.clone();
+        let mut mask = self.state.mask_buf.clone();
+        let mut map = self.state.map_buf.clone();
+        let mut index: Vec<usize> = (0..energy.len()).collect();
+        let mut marks = vec![false; energy.len()];
+        let (mut width, mut height) = (width, height);
+        for _ in 0..k {
+            let (path, _) = find_seam(
+                self.energy_mode,
+                &self.normalized(&energy),
+                map.as_deref(),
+                mask.as_deref(),
+                &gray,
+                width,
+                height,
+                dir,
+            );
+            for &idx in path.iter() {
+                marks[index[idx]] = true;
+            }
+            if let Some(mask) = mask.as_mut() {
+                remove_path_from_image(mask, &path, 1, dir, width);
+            }
+            if let Some(map) = map.as_mut() {
+                remove_path_from_image(map, &path, 1, dir, width);
+            }
+            remove_path_from_image(&mut energy, &path, 1, dir, width);
+            remove_path_from_image(&mut gray, &path, 1, dir, width);
+            if let Some(rgb) = rgb.as_mut() {
+                remove_path_from_image(rgb, &path, 3, dir, width);
+            }
+            recompute_energy(
+                self.energy.as_ref(),
+                self.recompute,
+                rgb.as_deref().unwrap_or(&gray),
+                &mut energy,
+                &path,
+                dir,
+                width,
+                height,
+            );
+            remove_path_from_image(&mut index, &path, 1, dir, width);
+            match dir {
+                Direction::Row => width -= 1,
+                Direction::Column => height -= 1,
+            }
+        }
+        marks
+    }
+
+    fn insert_seams(&mut self, k: usize, dir: Direction) {
+        let Dims { width, height } = self.dims();
+        let marks = self.find_seams(k, dir, width, height);
+        let no_channels = self.state.color.channel_count() as usize;
+        self.state.pixels = self
+            .state
+            .pixels
+            .insert_paths(&marks, no_channels, dir, width, height);
+        self.state.gray_buf =
+            insert_paths_into_image(&self.state.gray_buf, &marks, 1, dir, width, height);
+        self.state.energy_buf =
+            insert_paths_into_image(&self.state.energy_buf, &marks, 1, dir, width, height);
+        // An inserted pixel next to a masked one is masked just as much
+        if let Some(mask) = self.state.mask_buf.as_ref() {
+            let mask = duplicate_paths_in_image(mask, &marks, 1, dir, width, height);
+            self.state.mask_buf = Some(mask);
+        }
+        if let Some(map) = self.state.map_buf.as_ref() {
+            let map = insert_paths_into_image(map, &marks, 1, dir, width, height);
+            self.state.map_buf = Some(map);
+        }
+        if let Some(rgb) = self.state.rgb_buf.as_ref() {
+            let rgb = insert_paths_into_image(rgb, &marks, 3, dir, width, height);
+            self.state.rgb_buf = Some(rgb);
+        }
 
         let (width, height) = match dir {
-            Direction::Row => (width - 1, height),
-            Direction::Column => (width, height - 1),
+            Direction::Row => (width + k, height),
+            Direction::Column => (width, height + k),
         };
-        let width = width as u32;
-        let height = height as u32;
-        let new_img = match no_channels {
-            1 => GrayImage::from_vec(width, height, buf).unwrap().into(),
-            _ => RgbImage::from_vec(width, height, buf).unwrap().into(),
+        // The inserted seams are spread over the whole image, local updates
+        // would not be any cheaper.
+        if self.recompute != Recompute::Never {
+            let input = self
+                .state
+                .rgb_buf
+                .as_deref()
+                .unwrap_or(&self.state.gray_buf);
+            self.state.energy_buf = self.energy.energy(input, width, height);
+        }
+        self.state.dims = Dims::new(width, height);
+    }
+}
+
+/// Bring `energy_buf` up to date with `image`, the buffer `energy` reads,
+/// once `path` has been removed from both. `width` and `height` are the
+/// dimensions before the removal.
+#[allow(clippy::too_many_arguments)]
+fn recompute_energy(
+    energy: &dyn Energy,
+    recompute: Recompute,
+    image: &[u8],
+    energy_buf: &mut [f32],
+    path: &[usize],
+    dir: Direction,
+    width: usize,
+    height: usize,
+) {
+    // Position of the removed pixel on every line of the seam
+    let pos: Vec<usize> = match dir {
+        Direction::Row => path.iter().map(|idx| idx % width).collect(),
+        Direction::Column => path.iter().map(|idx| idx / width).collect(),
+    };
+    let (width, height) = match dir {
+        Direction::Row => (width - 1, height),
+        Direction::Column => (width, height - 1),
+    };
+    let radius = match (recompute, energy.radius()) {
+        (Recompute::Never, _) => return,
+        (Recompute::Local, Some(radius)) => radius,
+        // Without a radius any pixel may have changed
+        (Recompute::Local, None) | (Recompute::Full, _) => {
+            energy_buf.copy_from_slice(&energy.energy(image, width, height));
+            return;
+        }
+    };
+    let (outer, inner) = match dir {
+        Direction::Row => (height, width),
+        Direction::Column => (width, height),
+    };
+    // A pixel's energy changes if its window covers the two pixels that
+    // became neighbours on any line within reach.
+    let b = radius;
+    for line in 0..outer {
+        let near = &pos[line.saturating_sub(b)..=min(line + b, outer - 1)];
+        let start = near.iter().min().unwrap().saturating_sub(b);
+        let stop = min(near.iter().max().unwrap() + b, inner);
+        let (cols, rows) = match dir {
+            Direction::Row => (start..stop, line..line + 1),
+            Direction::Column => (line..line + 1, start..stop),
         };
-        self.img = Some(new_img);
+        energy.update(image, width, height, energy_buf, cols, rows);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn find_seam(
+    mode: EnergyMode,
+    energy: &[f32],
+    map: Option<&[f32]>,
+    mask: Option<&[i8]>,
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> (Vec<usize>, f32) {
+    let extra = pixel_energy(mode, energy, map, mask, width, height, dir);
+    let (cost_mat, path) = match mode {
+        EnergyMode::Backward => {
+            let cost_mat = match extra {
+                Some(extra) => {
+                    let energy: Vec<f32> = energy.iter().zip(extra).map(|(e, x)| e + x).collect();
+                    build_cost_matrix(&energy, width, height, dir)
+                }
+                None => build_cost_matrix(energy, width, height, dir),
+            };
+            let path = find_shortest_path(&cost_mat, width, height, dir);
+            (cost_mat, path)
+        }
+        EnergyMode::Forward => {
+            let cost_mat = build_forward_cost_matrix(gray, extra.as_deref(), width, height, dir);
+            let path = find_forward_shortest_path(&cost_mat, gray, width, height, dir);
+            (cost_mat, path)
+        }
+    };
+    let cost = cost_mat[path[0]];
+    (path, cost)
+}
+
+/// Largest forward cost of a pixel, both gradients of an 8-bit image
+const MAX_FORWARD_COST: f32 = 2.0 * 255.0;
+
+/// The energy the map and the masks add to every pixel, if any
+fn pixel_energy(
+    mode: EnergyMode,
+    energy: &[f32],
+    map: Option<&[f32]>,
+    mask: Option<&[i8]>,
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> Option<Vec<f32>> {
+    let Some(mask) = mask else {
+        return map.map(<[f32]>::to_vec);
+    };
+    let penalty = mask_penalty(mode, energy, map, width, height, dir);
+    let mask = mask.iter().map(|&m| m as f32 * penalty);
+    Some(match map {
+        Some(map) => map.iter().zip(mask).map(|(e, m)| e + m).collect(),
+        None => mask.collect(),
+    })
+}
+
+/// Energy added to (or subtracted from) masked pixels. It exceeds the
+/// spread of the pixel energy over a whole seam, so a seam crossing one
+/// more masked pixel always wins or loses against any other, twice over to
+/// leave room for rounding.
+fn mask_penalty(
+    mode: EnergyMode,
+    energy: &[f32],
+    map: Option<&[f32]>,
+    width: usize,
+    height: usize,
+    dir: Direction,
+) -> f32 {
+    let spread = |values: &mut dyn Iterator<Item = f32>| {
+        let (lo, hi) = values
+            .filter(|v| v.is_finite())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        if lo <= hi {
+            hi - lo
+        } else {
+            0.0
+        }
+    };
+    let map = map.unwrap_or(&[]);
+    let spread = match mode {
+        EnergyMode::Backward if map.is_empty() => spread(&mut energy.iter().copied()),
+        EnergyMode::Backward => spread(&mut energy.iter().zip(map).map(|(e, m)| e + m)),
+        EnergyMode::Forward => spread(&mut map.iter().copied()) + MAX_FORWARD_COST,
+    };
+    let len = match dir {
+        Direction::Row => height,
+        Direction::Column => width,
+    };
+    (2.0 * spread * len as f32).max(1.0)
+}
+
+fn is_supported(color: ColorType) -> bool {
+    matches!(
+        color,
+        ColorType::L8
+            | ColorType::La8
+            | ColorType::Rgb8
+            | ColorType::Rgba8
+            | ColorType::L16
+            | ColorType::La16
+            | ColorType::Rgb16
+            | ColorType::Rgba16
+            | ColorType::Rgb32F
+            | ColorType::Rgba32F
+    )
+}
+
+/// The raw samples of an image, for each sample type `DynamicImage` uses
+#[derive(Clone)]
+enum PixelBuf {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+impl PixelBuf {
+    fn remove_path(&mut self, path: &[usize], no_channels: usize, dir: Direction, width: usize) {
+        match self {
+            Self::U8(buf) => remove_path_from_image(buf, path, no_channels, dir, width),
+            Self::U16(buf) => remove_path_from_image(buf, path, no_channels, dir, width),
+            Self::F32(buf) => remove_path_from_image(buf, path, no_channels, dir, width),
+        }
+    }
+
+    fn transpose(&self, no_channels: usize, width: usize, height: usize) -> Self {
+        match self {
+            Self::U8(buf) => Self::U8(transpose(buf, no_channels, width, height)),
+            Self::U16(buf) => Self::U16(transpose(buf, no_channels, width, height)),
+            Self::F32(buf) => Self::F32(transpose(buf, no_channels, width, height)),
+        }
+    }
+
+    fn insert_paths(
+        &self,
+        marks: &[bool],
+        no_channels: usize,
+        dir: Direction,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        match self {
+            Self::U8(buf) => Self::U8(insert_paths_into_image(
+                buf,
+                marks,
+                no_channels,
+                dir,
+                width,
+                height,
+            )),
+            Self::U16(buf) => Self::U16(insert_paths_into_image(
+                buf,
+                marks,
+                no_channels,
+                dir,
+                width,
+                height,
+            )),
+            Self::F32(buf) => Self::F32(insert_paths_into_image(
+                buf,
+                marks,
+                no_channels,
+                dir,
+                width,
+                height,
+            )),
+        }
+    }
+}
+
+fn into_buffer(img: DynamicImage) -> (PixelBuf, ColorType) {
+    let color = img.color();
+    let buf = match img {
+        DynamicImage::ImageLuma8(img) => PixelBuf::U8(img.into_vec()),
+        DynamicImage::ImageLumaA8(img) => PixelBuf::U8(img.into_vec()),
+        DynamicImage::ImageRgb8(img) => PixelBuf::U8(img.into_vec()),
+        DynamicImage::ImageRgba8(img) => PixelBuf::U8(img.into_vec()),
+        DynamicImage::ImageLuma16(img) => PixelBuf::U16(img.into_vec()),
+        DynamicImage::ImageLumaA16(img) => PixelBuf::U16(img.into_vec()),
+        DynamicImage::ImageRgb16(img) => PixelBuf::U16(img.into_vec()),
+        DynamicImage::ImageRgba16(img) => PixelBuf::U16(img.into_vec()),
+        DynamicImage::ImageRgb32F(img) => PixelBuf::F32(img.into_vec()),
+        DynamicImage::ImageRgba32F(img) => PixelBuf::F32(img.into_vec()),
+        _ => unreachable!("pixel type checked in SeamCarver::new"),
+    };
+    (buf, color)
+}
+
+fn from_buffer(buf: PixelBuf, color: ColorType, width: usize, height: usize) -> DynamicImage {
+    let width = width as u32;
+    let height = height as u32;
+    match (buf, color) {
+        (PixelBuf::U8(buf), ColorType::L8) => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U8(buf), ColorType::La8) => {
+            DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U8(buf), ColorType::Rgb8) => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U8(buf), ColorType::Rgba8) => {
+            DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U16(buf), ColorType::L16) => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U16(buf), ColorType::La16) => {
+            DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U16(buf), ColorType::Rgb16) => {
+            DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::U16(buf), ColorType::Rgba16) => {
+            DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::F32(buf), ColorType::Rgb32F) => {
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        (PixelBuf::F32(buf), ColorType::Rgba32F) => {
+            DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, buf).unwrap())
+        }
+        _ => unreachable!("pixel type checked in SeamCarver::new"),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use image::io::Reader as ImageReader;
+    use crate::energy::{
+        ColorGradient, ColorSpace, Combine, DualGradient, Entropy, L1Gradient, Saliency,
+    };
+    use crate::sobel::{Border, Hog};
+    use image::{io::Reader as ImageReader, GenericImageView, RgbImage};
+    use rstest::rstest;
+    use std::{cell::RefCell, ops::Range, rc::Rc};
 
     #[test]
     fn test_basic_remove_01() {
@@ -161,7 +1282,7 @@ mod tests {
         let path = vec![2, 4, 7, 9];
         let no_channels = 1;
         let len = img.len();
-        remove_path_from_image(&mut img, path, no_channels, Direction::Row, len);
+        remove_path_from_image(&mut img, &path, no_channels, Direction::Row, len);
         let expected = vec![0, 1, 3, 5, 6, 8, 10];
         assert_eq!(expected.len(), img.len());
         assert_eq!(expected, img);
@@ -173,7 +1294,7 @@ mod tests {
         let path = vec![0, 2, 4, 7, 9, 10];
         let no_channels = 1;
         let len = img.len();
-        remove_path_from_image(&mut img, path, no_channels, Direction::Row, len);
+        remove_path_from_image(&mut img, &path, no_channels, Direction::Row, len);
         let expected = vec![1, 3, 5, 6, 8];
         assert_eq!(expected.len(), img.len());
         assert_eq!(expected, img);
@@ -187,7 +1308,7 @@ mod tests {
         let path = vec![0, 2, 4, 7, 9, 10];
         let no_channels = 2;
         let len = img.len();
-        remove_path_from_image(&mut img, path, no_channels, Direction::Row, len);
+        remove_path_from_image(&mut img, &path, no_channels, Direction::Row, len);
         let expected = vec![1, 1, 3, 3, 5, 5, 6, 6, 8, 8];
         assert_eq!(expected.len(), img.len());
         assert_eq!(expected, img);
@@ -207,7 +1328,7 @@ mod tests {
         let path = find_shortest_path(&energy, w, h, Direction::Column);
         let expected_path = vec![0, 1, 2, 3, 4];
         assert_eq!(path, expected_path);
-        remove_path_from_image(&mut energy, path, 1, Direction::Column, w);
+        remove_path_from_image(&mut energy, &path, 1, Direction::Column, w);
         #[rustfmt::skip]
         let expected = vec![
             1., 1., 1., 1., 1.,
@@ -246,7 +1367,7 @@ mod tests {
         let path = find_shortest_path(&energy, w, h, Direction::Column);
         let expected_path = vec![0, 1, 2, 3, 4];
         assert_eq!(path, expected_path);
-        remove_path_from_image(&mut img, path, 3, Direction::Column, w);
+        remove_path_from_image(&mut img, &path, 3, Direction::Column, w);
         #[rustfmt::skip]
         let expected = vec![
             1., 1., 1., 1., 1.,
@@ -262,14 +1383,911 @@ mod tests {
         assert_eq!(expected, img);
     }
 
+    #[test]
+    fn test_insert_row() {
+        let w = 3;
+        let h = 2;
+        #[rustfmt::skip]
+        let img: Vec<u8> = vec![
+            0, 10, 20,
+            30, 40, 50,
+        ];
+        #[rustfmt::skip]
+        let marks = vec![
+            true, false, false,
+            false, false, true,
+        ];
+        let res = insert_paths_into_image(&img, &marks, 1, Direction::Row, w, h);
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0, 5, 10, 20,
+            30, 40, 50, 50,
+        ];
+        assert_eq!(expected, res);
+    }
+
+    #[test]
+    fn test_insert_col_with_channels() {
+        let w = 2;
+        let h = 3;
+        #[rustfmt::skip]
+        let img: Vec<u8> = vec![
+            0, 0, 10, 10,
+            20, 20, 30, 30,
+            40, 40, 50, 50,
+        ];
+        #[rustfmt::skip]
+        let marks = vec![
+            false, true,
+            true, false,
+            false, false,
+        ];
+        let res = insert_paths_into_image(&img, &marks, 2, Direction::Column, w, h);
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            0, 0, 10, 10,
+            20, 20, 20, 20,
+            30, 30, 30, 30,
+            40, 40, 50, 50,
+        ];
+        assert_eq!(expected, res);
+    }
+
+    #[test]
+    fn test_duplicate_mask() {
+        let w = 3;
+        let h = 2;
+        #[rustfmt::skip]
+        let mask: Vec<i8> = vec![
+            PROTECT, 0, 0,
+            0, REMOVE, 0,
+        ];
+        #[rustfmt::skip]
+        let marks = vec![
+            true, false, false,
+            true, false, false,
+        ];
+        let res = duplicate_paths_in_image(&mask, &marks, 1, Direction::Row, w, h);
+        #[rustfmt::skip]
+        let expected: Vec<i8> = vec![
+            PROTECT, PROTECT, 0, 0,
+            0, 0, REMOVE, 0,
+        ];
+        assert_eq!(expected, res);
+    }
+
+    #[test]
+    fn test_find_seams_are_disjoint() {
+        let img = ImageReader::open("./test_data/src/valve.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let mut carver = SeamCarver::new(img, width, height).unwrap();
+        carver.prepare().unwrap();
+        let k = 20;
+        let marks = carver.find_seams(k, Direction::Row, width, height);
+        for row in marks.chunks(width) {
+            assert_eq!(k, row.iter().filter(|&&m| m).count());
+        }
+        let marks = carver.find_seams(k, Direction::Column, width, height);
+        for c in 0..width {
+            let count = (0..height).filter(|r| marks[r * width + c]).count();
+            assert_eq!(k, count);
+        }
+    }
+
+    #[test]
+    fn test_enlarge() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let new_width = width * 6 / 5;
+        let new_height = height + 10;
+        let new_img = SeamCarver::new(img, new_width, new_height)
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(new_width as u32, new_img.width());
+        assert_eq!(new_height as u32, new_img.height());
+        let fname = "./test_data/outputs/valve-sc-enlarged.png";
+        new_img.save(fname).unwrap();
+    }
+
+    #[test]
+    fn test_forward_energy() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let new_img = SeamCarver::new(img, width * 4 / 5, height * 9 / 10)
+            .unwrap()
+            .energy_mode(EnergyMode::Forward)
+            .apply()
+            .unwrap();
+        assert_eq!((width * 4 / 5) as u32, new_img.width());
+        assert_eq!((height * 9 / 10) as u32, new_img.height());
+        let fname = "./test_data/outputs/valve-sc-forward.png";
+        new_img.save(fname).unwrap();
+    }
+
+    /// Fails the test if the energy is ever computed
+    struct Unused;
+
+    impl Energy for Unused {
+        fn energy(&self, _image: &[u8], _width: usize, _height: usize) -> Vec<f32> {
+            panic!("the energy is computed")
+        }
+    }
+
+    #[test]
+    fn test_forward_energy_skips_energy() {
+        let buf: Vec<u8> = (0..20 * 12 * 3).map(|v| (v * 37 % 256) as u8).collect();
+        let img: DynamicImage = RgbImage::from_raw(20, 12, buf).unwrap().into();
+        let expected = SeamCarver::new(img.clone(), 16, 15)
+            .unwrap()
+            .energy_mode(EnergyMode::Forward)
+            .apply()
+            .unwrap();
+        let new_img = SeamCarver::new(img, 16, 15)
+            .unwrap()
+            .energy_mode(EnergyMode::Forward)
+            .energy(Unused)
+            .blur(Gaussian::new(1.0).unwrap())
+            .apply()
+            .unwrap();
+        assert_eq!(expected, new_img);
+    }
+
+    /// Carve a few seams and check the energy map matches a full recompute
+    fn check_local_recompute<E: Energy + Clone + 'static>(energy: E, dir: Direction) {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let mut carver = SeamCarver::new(img, 1, 1)
+            .unwrap()
+            .recompute(Recompute::Local)
+            .energy(energy.clone());
+        carver.prepare().unwrap();
+        for _ in 0..5 {
+            carver.remove_seam(dir);
+            let Dims { width, height } = carver.dims();
+            let state = &carver.state;
+            let input = state.rgb_buf.as_deref().unwrap_or(&state.gray_buf);
+            let expected = energy.energy(input, width, height);
+            assert_eq!(expected, carver.state.energy_buf);
+        }
+    }
+
+    #[rstest]
+    fn test_local_recompute(
+        #[values(Kernel::X3, Kernel::X5)] kernel_type: Kernel,
+        #[values(Direction::Row, Direction::Column)] dir: Direction,
+    ) {
+        check_local_recompute(Sobel::new().kernel(kernel_type), dir);
+        check_local_recompute(Hog::new().kernel(kernel_type).cell(5), dir);
+        let sobel = Sobel::new().kernel(kernel_type);
+        check_local_recompute(sobel.border(Border::Reflect), dir);
+        check_local_recompute(sobel.border(Border::Wrap), dir);
+    }
+
+    #[rstest]
+    fn test_local_recompute_gradients(#[values(Direction::Row, Direction::Column)] dir: Direction) {
+        check_local_recompute(L1Gradient, dir);
+        check_local_recompute(DualGradient, dir);
+        check_local_recompute(ColorGradient::new().combine(Combine::Max), dir);
+        check_local_recompute(ColorGradient::new().space(ColorSpace::Lab), dir);
+        check_local_recompute(Entropy::new().window(5).gradient(true), dir);
+        check_local_recompute(Blurred::new(Sobel::new(), Gaussian::new(1.0).unwrap()), dir);
+    }
+
+    /// Count the pixels of the red stripe left on every row
+    fn carve_red_stripe(energy: impl Energy + 'static) -> Vec<usize> {
+        // A red stripe on a green of the same luminance, next to a texture
+        // in the blue channel that only shows in grayscale
+        let img = RgbImage::from_fn(40, 20, |x, y| match x {
+            18 | 19 => image::Rgb([200, 0, 0]),
+            _ => image::Rgb([0, 59, ((x * 7 + y * 3) % 5 * 30) as u8]),
+        });
+        let new_img = SeamCarver::new(img.into(), 30, 20)
+            .unwrap()
+            .energy(energy)
+            .apply()
+            .unwrap()
+            .into_rgb8();
+        new_img
+            .rows()
+            .map(|row| row.filter(|p| p[0] == 200).count())
+            .collect()
+    }
+
+    #[test]
+    fn test_color_energy() {
+        assert!(carve_red_stripe(DualGradient).iter().any(|&n| n < 2));
+        for combine in [Combine::Sum, Combine::Max, Combine::L2] {
+            let energy = ColorGradient::new().combine(combine);
+            assert_eq!(vec![2; 20], carve_red_stripe(energy));
+        }
+        let energy = ColorGradient::new().space(ColorSpace::Lab);
+        assert_eq!(vec![2; 20], carve_red_stripe(energy));
+    }
+
+    #[test]
+    fn test_saliency() {
+        let src_path = "./test_data/src/saturn.jpg";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let new_img = SeamCarver::new(img, width * 9 / 10, height)
+            .unwrap()
+            .energy(Saliency::new().sobel(Sobel::new()))
+            .recompute(Recompute::Never)
+            .apply()
+            .unwrap();
+        assert_eq!((width * 9 / 10) as u32, new_img.width());
+        let fname = "./test_data/outputs/saturn-sc-saliency.png";
+        new_img.save(fname).unwrap();
+    }
+
+    #[rstest]
+    fn test_blur_and_normalize(
+        #[values(Normalize::MinMax, Normalize::Percentile(0.99), Normalize::Log)]
+        normalize: Normalize,
+    ) {
+        let src_path = "./test_data/src/saturn.jpg";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let new_img = SeamCarver::new(img, width * 9 / 10, height)
+            .unwrap()
+            .blur(Gaussian::new(1.5).unwrap())
+            .normalize(normalize)
+            .recompute(Recompute::Never)
+            .apply()
+            .unwrap();
+        assert_eq!((width * 9 / 10) as u32, new_img.width());
+        if normalize == Normalize::Percentile(0.99) {
+            let fname = "./test_data/outputs/saturn-sc-blur.png";
+            new_img.save(fname).unwrap();
+        }
+    }
+
+    #[rstest]
+    fn test_transposed_layout(
+        #[values(EnergyMode::Backward, EnergyMode::Forward)] energy_mode: EnergyMode,
+        #[values(Kernel::X3, Kernel::Roberts)] kernel: Kernel,
+        #[values(-4, 3)] seams: isize,
+    ) {
+        let src_path = "./test_data/src/saturn.jpg";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let carve = |layout| {
+            SeamCarver::new(img.clone(), width, height.wrapping_add_signed(seams))
+                .unwrap()
+                .energy_mode(energy_mode)
+                .energy(Sobel::new().kernel(kernel))
+                .layout(layout)
+                .apply()
+                .unwrap()
+        };
+        assert!(carve(Layout::Strided) == carve(Layout::Transposed));
+    }
+
+    #[rstest]
+    #[case(|| Box::new(Sobel::new().kernel(Kernel::X7).border(Border::Reflect)) as Box<dyn Energy>, true)]
+    #[case(|| Box::new(Sobel::new().kernel(Kernel::Roberts)) as Box<dyn Energy>, true)]
+    #[case(|| Box::new(L1Gradient) as Box<dyn Energy>, true)]
+    #[case(|| Box::new(DualGradient) as Box<dyn Energy>, true)]
+    #[case(|| Box::new(ColorGradient::new().space(ColorSpace::Lab)) as Box<dyn Energy>, true)]
+    #[case(|| Box::new(Entropy::new().window(5).gradient(true)) as Box<dyn Energy>, false)]
+    #[case(|| Box::new(Saliency::new().sobel(Sobel::new())) as Box<dyn Energy>, false)]
+    #[case(|| Box::new(Blurred::new(Sobel::new(), Gaussian::new(1.5).unwrap())) as Box<dyn Energy>, false)]
+    #[case(|| Box::new(Hog::new().cell(5).bins(5)) as Box<dyn Energy>, false)]
+    fn test_orientation_invariant(#[case] make: fn() -> Box<dyn Energy>, #[case] invariant: bool) {
+        let energy = make();
+        assert_eq!(invariant, energy.orientation_invariant());
+        let img = random_image(31, 23, 4);
+        let carve = |layout| {
+            SeamCarver::new(img.clone(), 31, 17)
+                .unwrap()
+                .energy(make())
+                .layout(layout)
+                .apply()
+                .unwrap()
+        };
+        assert!(carve(Layout::Strided) == carve(Layout::Transposed));
+        if !invariant {
+            return;
+        }
+        let c = energy.channels();
+        let buf = match c {
+            1 => img.to_luma8().into_raw(),
+            _ => img.to_rgb8().into_raw(),
+        };
+        let energy_t = energy.energy(&transpose(&buf, c, 31, 23), 23, 31);
+        let expected = transpose(&energy.energy(&buf, 31, 23), 1, 31, 23);
+        assert_eq!(expected, energy_t);
+    }
+
+    #[test]
+    fn test_blur_depends_on_orientation() {
+        let mut differ = 0;
+        for seed in 0..8 {
+            for (width, height) in [(97, 61), (128, 80), (150, 113)] {
+                let gaussian = Gaussian::new(0.8 + seed as f32 * 0.6).unwrap();
+                let img = random_image(width as u32, height as u32, seed);
+                let buf = img.to_rgb8().into_raw();
+                let blur = |buf: &[u8], w, h| gaussian.blur_region(buf, w, h, 3, 0..w, 0..h);
+                let blurred = transpose(&blur(&buf, width, height), 3, width, height);
+                let transposed = blur(&transpose(&buf, 3, width, height), height, width);
+                differ += (blurred != transposed) as usize;
+            }
+        }
+        // Rounding differs on a few pixels of some images only
+        assert!(differ > 0);
+    }
+
+    /// An energy without a radius, which has to be fully recomputed
+    #[derive(Clone)]
+    struct RowMean;
+
+    impl Energy for RowMean {
+        fn energy(&self, image: &[u8], width: usize, _height: usize) -> Vec<f32> {
+            image
+                .chunks(width)
+                .flat_map(|row| {
+                    let mean = row.iter().map(|&v| v as f32).sum::<f32>() / width as f32;
+                    row.iter().map(move |&v| (v as f32 - mean).abs())
+                })
+                .collect()
+        }
+    }
+
+    #[rstest]
+    fn test_recompute_without_radius(#[values(Direction::Row, Direction::Column)] dir: Direction) {
+        check_local_recompute(RowMean, dir);
+    }
+
+    fn rect_mask(width: usize, height: usize, cols: Range<usize>, rows: Range<usize>) -> Mask {
+        let data = (0..width * height)
+            .map(|idx| cols.contains(&(idx % width)) && rows.contains(&(idx / width)))
+            .collect();
+        Mask::new(width, height, data).unwrap()
+    }
+
+    #[test]
+    fn test_remove_object() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let mask = rect_mask(width, height, 300..320, 100..200);
+        let mut carver = SeamCarver::new(img, width, height)
+            .unwrap()
+            .remove_mask(mask)
+            .unwrap();
+        carver.prepare().unwrap();
+        carver.remove_object();
+        assert!(!carver.has_object());
+        let dims = carver.dims();
+        assert_eq!(height, dims.height);
+        assert!(dims.width <= width - 20);
+
+        let new_img = carver.apply().unwrap();
+        assert_eq!(width as u32, new_img.width());
+        assert_eq!(height as u32, new_img.height());
+        let fname = "./test_data/outputs/valve-sc-removed.png";
+        new_img.save(fname).unwrap();
+    }
+
+    #[test]
+    fn test_remove_object_wide() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let mask = rect_mask(width, height, 100..400, 50..60);
+        let mut carver = SeamCarver::new(img, width, height)
+            .unwrap()
+            .remove_mask(mask)
+            .unwrap();
+        carver.prepare().unwrap();
+        carver.remove_object();
+        assert!(!carver.has_object());
+        let dims = carver.dims();
+        assert_eq!(width, dims.width);
+        assert!(dims.height <= height - 10);
+    }
+
+    #[test]
+    fn test_protect_mask() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let mask = rect_mask(width, height, 250..450, 0..height);
+        let mut carver = SeamCarver::new(img, width, height)
+            .unwrap()
+            .protect_mask(mask)
+            .unwrap();
+        carver.prepare().unwrap();
+        carver.retarget(width, width * 3 / 4, Direction::Row);
+        let protected = carver.state.mask_buf.unwrap();
+        let count = protected.iter().filter(|&&v| v == PROTECT).count();
+        assert_eq!(200 * height, count);
+    }
+
+    #[rstest]
+    fn test_protect_mask_outweighs_energy(
+        #[values(EnergyMode::Backward, EnergyMode::Forward)] energy_mode: EnergyMode,
+    ) {
+        // Flat on the left, stripes whose 7x7 Sobel energy is far above
+        // any fixed penalty on the right
+        let buf = (0..20 * 30)
+            .map(|idx| match idx % 30 {
+                c if c < 10 => 128,
+                c => (c % 2 * 255) as u8,
+            })
+            .collect();
+        let img = DynamicImage::from(image::GrayImage::from_raw(30, 20, buf).unwrap());
+        let protected = |carver: SeamCarver| {
+            let mut carver = carver.energy_mode(energy_mode);
+            carver.prepare().unwrap();
+            carver.retarget(30, 25, Direction::Row);
+            let mask = carver.state.mask_buf.unwrap();
+            mask.iter().filter(|&&v| v == PROTECT).count()
+        };
+        let carver = SeamCarver::new(img.clone(), 25, 20)
+            .unwrap()
+            .energy(Sobel::new().kernel(Kernel::X7))
+            .protect_mask(rect_mask(30, 20, 0..8, 0..20))
+            .unwrap();
+        assert_eq!(8 * 20, protected(carver));
+
+        let data = (0..20 * 30)
+            .map(|idx| if idx % 30 < 10 { 0.0 } else { 1.0 })
+            .collect();
+        let map = EnergyMap::new(30, 20, data).unwrap();
+        let carver = SeamCarver::new(img, 25, 20)
+            .unwrap()
+            .protect_mask(rect_mask(30, 20, 0..10, 0..20))
+            .unwrap()
+            .energy_map(map, MapBlend::Add(1e6))
+            .unwrap();
+        assert_eq!(10 * 20, protected(carver));
+    }
+
+    #[rstest]
+    fn test_energy_map_replace(
+        #[values(EnergyMode::Backward, EnergyMode::Forward)] energy_mode: EnergyMode,
+    ) {
+        // Only columns 10 to 12 are cheap
+        let img = random_image(30, 20, 5);
+        let data = (0..30 * 20)
+            .map(|idx| {
+                if (10..13).contains(&(idx % 30)) {
+                    0.0
+                } else {
+                    1e3
+                }
+            })
+            .collect();
+        let map = EnergyMap::new(30, 20, data).unwrap();
+        let new_img = SeamCarver::new(img.clone(), 27, 20)
+            .unwrap()
+            .energy_mode(energy_mode)
+            .energy_map(map, MapBlend::Replace)
+            .unwrap()
+            .apply()
+            .unwrap();
+        let mut expected = img.to_rgb8().into_raw();
+        let mut width = 30;
+        for _ in 0..3 {
+            let path: Vec<usize> = (0..20).map(|r| 10 + r * width).collect();
+            remove_path_from_image(&mut expected, &path, 3, Direction::Row, width);
+            width -= 1;
+        }
+        assert_eq!(expected, new_img.into_rgb8().into_raw());
+    }
+
+    #[test]
+    fn test_energy_map_add() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = img.crop_imm(200, 100, 120, 90);
+        let expected = SeamCarver::new(img.clone(), 100, 80)
+            .unwrap()
+            .apply()
+            .unwrap();
+        let map = EnergyMap::new(120, 90, vec![1.0; 120 * 90]).unwrap();
+        let new_img = SeamCarver::new(img.clone(), 100, 80)
+            .unwrap()
+            .energy_map(map, MapBlend::Add(0.0))
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(expected, new_img);
+
+        // A heavy map keeps seams away like a protection mask does
+        let protect = rect_mask(120, 90, 40..80, 0..90);
+        let data = protect.as_slice().iter().map(|&m| m as u8 as f32).collect();
+        let map = EnergyMap::new(120, 90, data).unwrap();
+        let mut carver = SeamCarver::new(img.clone(), 100, 90)
+            .unwrap()
+            .energy_map(map, MapBlend::Add(1e4))
+            .unwrap();
+        carver.prepare().unwrap();
+        carver.retarget(120, 100, Direction::Row);
+        let map = carver.state.map_buf.unwrap();
+        assert_eq!(40 * 90, map.iter().filter(|&&v| v > 0.0).count());
+
+        // A map as low as the removal mask removes nothing
+        let mut data = vec![0.0; 120 * 90];
+        data[45 * 120 + 60] = 1.0;
+        let map = EnergyMap::new(120, 90, data).unwrap();
+        let new_img = SeamCarver::new(img.clone(), 120, 90)
+            .unwrap()
+            .enlarge(Enlarge::Deny)
+            .energy_map(map, MapBlend::Add(-1e5))
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(img, new_img);
+
+        let map = EnergyMap::new(90, 120, vec![0.0; 120 * 90]).unwrap();
+        let res = SeamCarver::new(img, 100, 90)
+            .unwrap()
+            .energy_map(map, MapBlend::Replace);
+        let expected = CarveError::InvalidDimensions {
+            expected: (120, 90),
+            found: (90, 120),
+        };
+        assert_eq!(Some(expected), res.err());
+    }
+
+    #[rstest]
+    #[case(|img: &DynamicImage| img.to_luma8().into())]
+    #[case(|img: &DynamicImage| img.to_luma_alpha8().into())]
+    #[case(|img: &DynamicImage| img.to_rgb8().into())]
+    #[case(|img: &DynamicImage| img.to_rgba8().into())]
+    #[case(|img: &DynamicImage| img.to_luma16().into())]
+    #[case(|img: &DynamicImage| img.to_luma_alpha16().into())]
+    #[case(|img: &DynamicImage| img.to_rgb16().into())]
+    #[case(|img: &DynamicImage| img.to_rgba16().into())]
+    #[case(|img: &DynamicImage| img.to_rgb32f().into())]
+    #[case(|img: &DynamicImage| img.to_rgba32f().into())]
+    fn test_pixel_types(#[case] convert: fn(&DynamicImage) -> DynamicImage) {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = convert(&img.crop_imm(200, 100, 120, 90));
+        let color = img.color();
+
+        let new_img = SeamCarver::new(img.clone(), 100, 100)
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(color, new_img.color());
+        assert_eq!((100, 100), (new_img.width(), new_img.height()));
+
+        let new_img = SeamCarver::new(img, 140, 80).unwrap().apply().unwrap();
+        assert_eq!(color, new_img.color());
+        assert_eq!((140, 80), (new_img.width(), new_img.height()));
+    }
+
+    #[rstest]
+    fn test_seam_order(
+        #[values(SeamOrder::ColumnsThenRows, SeamOrder::Greedy, SeamOrder::Optimal)]
+        order: SeamOrder,
+    ) {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = img.crop_imm(200, 100, 120, 90);
+        let new_img = SeamCarver::new(img, 100, 80)
+            .unwrap()
+            .order(order)
+            .apply()
+            .unwrap();
+        assert_eq!((100, 80), (new_img.width(), new_img.height()));
+    }
+
+    #[test]
+    fn test_optimal_order_is_cheapest() {
+        let src_path = "./test_data/src/valve.png";
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = img.crop_imm(200, 100, 120, 90);
+        let (rows, cols) = (10, 20);
+
+        let mut carver = SeamCarver::new(img.clone(), 100, 80).unwrap();
+        carver.prepare().unwrap();
+        let mut in_order: f32 = (0..rows)
+            .map(|_| carver.remove_seam(Direction::Column))
+            .sum();
+        in_order += (0..cols)
+            .map(|_| carver.remove_seam(Direction::Row))
+            .sum::<f32>();
+
+        let mut carver = SeamCarver::new(img.clone(), 100, 80).unwrap();
+        carver.prepare().unwrap();
+        let mut greedy = carver.carve_greedy(rows, cols);
+        let Dims { width, height } = carver.dims();
+        greedy += (0..width - 100)
+            .map(|_| carver.remove_seam(Direction::Row))
+            .sum::<f32>();
+        greedy += (0..height - 80)
+            .map(|_| carver.remove_seam(Direction::Column))
+            .sum::<f32>();
+
+        let mut carver = SeamCarver::new(img, 100, 80).unwrap();
+        carver.prepare().unwrap();
+        let optimal = carver.carve_optimal(rows, cols);
+        assert_eq!(Dims::new(100, 80), carver.dims());
+        assert!(optimal <= in_order);
+        assert!(optimal <= greedy);
+    }
+
+    #[test]
+    fn test_errors() {
+        let img = DynamicImage::new_rgb8(20, 10);
+        let res = SeamCarver::new(img.clone(), 0, 10);
+        assert_eq!(Some(CarveError::ZeroSizeTarget), res.err());
+
+        let mask = Mask::new(10, 20, vec![false; 200]).unwrap();
+        let res = SeamCarver::new(img.clone(), 10, 10)
+            .unwrap()
+            .protect_mask(mask);
+        let expected = CarveError::InvalidDimensions {
+            expected: (20, 10),
+            found: (10, 20),
+        };
+        assert_eq!(Some(expected), res.err());
+
+        // Windows are truncated at the border, whatever their size
+        let img = DynamicImage::new_luma8(20, 2);
+        let res = SeamCarver::new(img, 10, 2).unwrap().apply();
+        assert!(res.is_ok());
+        let img = DynamicImage::new_luma8(40, 12);
+        let res = SeamCarver::new(img, 30, 12)
+            .unwrap()
+            .energy(Hog::new())
+            .blur(Gaussian::new(5.0).unwrap())
+            .apply();
+        assert!(res.is_ok());
+
+        let img = DynamicImage::new_luma8(20, 2);
+        let res = SeamCarver::new(img, 10, 2)
+            .unwrap()
+            .energy(MinSize(3))
+            .apply();
+        let expected = CarveError::ImageTooSmall {
+            width: 20,
+            height: 2,
+            kernel: 3,
+        };
+        assert_eq!(Some(expected), res.err());
+
+        let img = DynamicImage::new_luma8(20, 10);
+        let res = SeamCarver::new(img.clone(), 20, 12)
+            .unwrap()
+            .enlarge(Enlarge::Deny)
+            .apply();
+        let expected = CarveError::EnlargementDenied {
+            from: (20, 10),
+            to: (20, 12),
+        };
+        assert_eq!(Some(expected), res.err());
+
+        let res = SeamCarver::new(img, 20, 12)
+            .unwrap()
+            .kernel(Kernel::X5)
+            .apply();
+        assert!(res.is_ok());
+        let img = DynamicImage::new_luma8(20, 4);
+        let res = SeamCarver::new(img, 10, 4)
+            .unwrap()
+            .kernel(Kernel::X5)
+            .apply();
+        assert!(res.is_ok());
+        let img = DynamicImage::new_luma8(20, 10);
+        let res = SeamCarver::new(img.clone(), 10, 10)
+            .unwrap()
+            .kernel(Kernel::X5)
+            .energy(L1Gradient)
+            .apply();
+        assert_eq!(Some(CarveError::KernelNotUsed), res.err());
+        let res = SeamCarver::new(img.clone(), 10, 10)
+            .unwrap()
+            .energy_mode(EnergyMode::Forward)
+            .kernel(Kernel::X5)
+            .apply();
+        assert_eq!(Some(CarveError::KernelNotUsed), res.err());
+        let map = EnergyMap::new(20, 10, vec![0.0; 200]).unwrap();
+        let res = SeamCarver::new(img, 10, 10)
+            .unwrap()
+            .kernel(Kernel::X5)
+            .energy_map(map, MapBlend::Replace)
+            .unwrap()
+            .apply();
+        assert_eq!(Some(CarveError::KernelNotUsed), res.err());
+    }
+
+    #[test]
+    fn test_kernel_keeps_energy() {
+        let img = random_image(30, 20, 9);
+        let carve = |carver: SeamCarver| carver.apply().unwrap();
+        let new = || SeamCarver::new(img.clone(), 24, 17).unwrap();
+        let hog = Hog::new().cell(5).border(Border::Reflect);
+        let expected = carve(new().energy(hog.kernel(Kernel::X5)));
+        assert!(expected == carve(new().energy(hog).kernel(Kernel::X5)));
+        assert!(expected == carve(new().kernel(Kernel::X5).energy(hog)));
+        assert!(expected != carve(new().kernel(Kernel::X5)));
+
+        let sobel = Sobel::new().border(Border::Reflect);
+        let expected = carve(new().energy(sobel.kernel(Kernel::X7)));
+        assert!(expected == carve(new().energy(sobel).kernel(Kernel::X7)));
+        let saliency = Saliency::new().sobel(sobel.kernel(Kernel::Scharr));
+        let expected = carve(new().energy(saliency));
+        let saliency = Saliency::new().sobel(sobel);
+        assert!(expected == carve(new().energy(saliency).kernel(Kernel::Scharr)));
+    }
+
+    /// An energy refusing images smaller than its window
+    struct MinSize(usize);
+
+    impl Energy for MinSize {
+        fn energy(&self, image: &[u8], _width: usize, _height: usize) -> Vec<f32> {
+            vec![0.0; image.len()]
+        }
+
+        fn min_size(&self) -> usize {
+            self.0
+        }
+    }
+
+    #[rstest]
+    fn test_progress(
+        #[values(SeamOrder::ColumnsThenRows, SeamOrder::Greedy, SeamOrder::Optimal)]
+        order: SeamOrder,
+        #[values((7, 4), (13, 4), (7, 12))] target: (usize, usize),
+    ) {
+        let img = random_image(11, 9, 3);
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let (width, height) = target;
+        let new_img = SeamCarver::new(img, width, height)
+            .unwrap()
+            .order(order)
+            .progress({
+                let calls = calls.clone();
+                move |done, total| calls.borrow_mut().push((done, total))
+            })
+            .apply()
+            .unwrap();
+        assert_eq!((width as u32, height as u32), new_img.dimensions());
+
+        let calls = calls.borrow();
+        let total = 11usize.abs_diff(width) + 9usize.abs_diff(height);
+        assert!(calls.iter().all(|&(_, t)| t == total));
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(Some(&(total, total)), calls.last());
+    }
+
+    /// A noise image from a linear congruential generator
+    fn random_image(width: u32, height: u32, seed: u32) -> DynamicImage {
+        let mut state = seed;
+        let buf = (0..width * height * 3)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 24) as u8
+            })
+            .collect();
+        RgbImage::from_raw(width, height, buf).unwrap().into()
+    }
+
+    #[rstest]
+    fn test_carve_to_single_pixel(
+        #[values(EnergyMode::Backward, EnergyMode::Forward)] energy_mode: EnergyMode,
+        #[values(Recompute::Never, Recompute::Local, Recompute::Full)] recompute: Recompute,
+        #[values((1, 17), (17, 1), (1, 1))] target: (usize, usize),
+    ) {
+        for seed in 0..4 {
+            let img = random_image(13 + seed, 11, seed);
+            let (width, height) = target;
+            let new_img = SeamCarver::new(img, width, height)
+                .unwrap()
+                .energy_mode(energy_mode)
+                .recompute(recompute)
+                .apply()
+                .unwrap();
+            assert_eq!((width as u32, height as u32), new_img.dimensions());
+        }
+    }
+
+    #[rstest]
+    fn test_carve_single_pixel_input(
+        #[values(EnergyMode::Backward, EnergyMode::Forward)] energy_mode: EnergyMode,
+        #[values(
+            ((20, 1), (10, 1)),
+            ((1, 20), (1, 10)),
+            ((20, 1), (27, 1)),
+            ((1, 20), (1, 27)),
+            ((1, 20), (1, 1)),
+            ((1, 1), (3, 2))
+        )]
+        dims: ((u32, u32), (usize, usize)),
+    ) {
+        let ((width, height), (new_width, new_height)) = dims;
+        let energies: Vec<Box<dyn Energy>> = vec![
+            Box::new(Sobel::new()),
+            Box::new(Sobel::new().kernel(Kernel::X7).border(Border::Reflect)),
+            Box::new(Sobel::new().border(Border::Wrap)),
+            Box::new(L1Gradient),
+            Box::new(DualGradient),
+            Box::new(ColorGradient::new().space(ColorSpace::Lab)),
+            Box::new(Entropy::new().gradient(true)),
+            Box::new(Hog::new()),
+            Box::new(Saliency::new().sobel(Sobel::new())),
+            Box::new(Blurred::new(Sobel::new(), Gaussian::new(2.0).unwrap())),
+        ];
+        for energy in energies {
+            let img = random_image(width, height, 7);
+            let new_img = SeamCarver::new(img, new_width, new_height)
+                .unwrap()
+                .energy_mode(energy_mode)
+                .energy(energy)
+                .apply()
+                .unwrap();
+            assert_eq!((new_width as u32, new_height as u32), new_img.dimensions());
+        }
+    }
+
+    #[rstest]
+    fn test_carve_to_single_pixel_ordered(
+        #[values(SeamOrder::Greedy, SeamOrder::Optimal)] order: SeamOrder,
+        #[values((1, 5), (5, 1), (1, 1))] target: (usize, usize),
+    ) {
+        let img = random_image(9, 7, 42);
+        let (width, height) = target;
+        let new_img = SeamCarver::new(img, width, height)
+            .unwrap()
+            .order(order)
+            .apply()
+            .unwrap();
+        assert_eq!((width as u32, height as u32), new_img.dimensions());
+    }
+
+    #[rstest]
+    fn test_enlarge_single_pixel_line(
+        #[values(Direction::Row, Direction::Column)] dir: Direction,
+        #[values(Recompute::Never, Recompute::Local)] recompute: Recompute,
+    ) {
+        let img = random_image(9, 7, 7);
+        let mut carver = SeamCarver::new(img, 9, 7).unwrap().recompute(recompute);
+        carver.prepare().unwrap();
+        let (from, to) = match dir {
+            Direction::Row => (9, 1),
+            Direction::Column => (7, 1),
+        };
+        carver.retarget(from, to, dir);
+        carver.retarget(to, from + 3, dir);
+        let expected = match dir {
+            Direction::Row => Dims::new(12, 7),
+            Direction::Column => Dims::new(9, 10),
+        };
+        assert_eq!(expected, carver.dims());
+    }
+
     #[test]
     fn test_full_cycle() {
-        let src_path = format!("./test_data/src/broadway_tower.jpg");
+        let src_path = "./test_data/src/broadway_tower.jpg";
         let img = ImageReader::open(src_path).unwrap().decode().unwrap();
         let width = img.width() as usize;
         let height = img.height() as usize;
-        let new_img = SeamCarver::new(img, width / 2, height).apply();
-        let fname = format!("./test_data/outputs/broadway_tower-sc-2.png");
+        let new_img = SeamCarver::new(img, width / 2, height)
+            .unwrap()
+            .apply()
+            .unwrap();
+        let fname = "./test_data/outputs/broadway_tower-sc-2.png";
         new_img.save(fname).unwrap();
     }
 }
@@ -1,9 +1,36 @@
-use std::cmp::{max, min};
+use std::{
+    cmp::{max, min},
+    f32::consts::PI,
+    ops::Range,
+    sync::OnceLock,
+};
 
-#[derive(Debug, Clone, Copy)]
+use crate::energy::Energy;
+
+/// Below this many pixels, splitting the rows across threads costs more
+/// than it saves
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_LEN: usize = 1 << 16;
+
+/// The column and row vectors of a separable kernel
+type Factors = (Vec<i32>, Vec<i32>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Kernel {
     X5,
     X3,
+    /// 7x7 Sobel, generated from binomial coefficients
+    X7,
+    /// 3x3 Scharr, closer to rotation invariant than the 3x3 Sobel
+    Scharr,
+    /// 3x3 Prewitt, a derivative without smoothing
+    Prewitt,
+    /// 2x2 Roberts cross, differentiating along the diagonals. The most
+    /// precise on line art, the most sensitive to noise.
+    Roberts,
+    /// 5x5 Laplacian of Gaussian. A single kernel, the energy is the
+    /// absolute value of the second derivative.
+    LoG,
 }
 
 impl Kernel {
@@ -11,7 +38,25 @@ impl Kernel {
     fn size(self) -> usize {
         match self {
             Self::X5 => 5,
-            Self::X3 => 3,
+            Self::X3 | Self::Scharr | Self::Prewitt => 3,
+            Self::X7 => 7,
+            // Padded to 3x3 so the kernel has a center
+            Self::Roberts => 3,
+            Self::LoG => 5,
+        }
+    }
+
+    /// Used to name the test outputs
+    #[cfg(test)]
+    fn name(self) -> &'static str {
+        match self {
+            Self::X5 => "kernel5x5",
+            Self::X3 => "kernel3x3",
+            Self::X7 => "kernel7x7",
+            Self::Scharr => "scharr3x3",
+            Self::Prewitt => "prewitt3x3",
+            Self::Roberts => "roberts2x2",
+            Self::LoG => "log5x5",
         }
     }
 
@@ -21,28 +66,143 @@ impl Kernel {
                 2, 2, 4, 2, 2, 1, 1, 2, 1, 1, 0, 0, 0, 0, 0, -1, -1, -2, -1, -1, -2, -2, -4, -2, -2,
             ],
             Self::X3 => vec![-1, -2, -1, 0, 0, 0, 1, 2, 1],
+            Self::X7 => sobel_kernel(7),
+            Self::Scharr => vec![-3, -10, -3, 0, 0, 0, 3, 10, 3],
+            Self::Prewitt => vec![-1, -1, -1, 0, 0, 0, 1, 1, 1],
+            Self::Roberts => vec![0, 0, 0, 0, 1, 0, 0, 0, -1],
+            Self::LoG => vec![
+                0, 0, -1, 0, 0, 0, -1, -2, -1, 0, -1, -2, 16, -2, -1, 0, -1, -2, -1, 0, 0, 0, -1,
+                0, 0,
+            ],
         }
     }
 
+    /// The x and y kernels, built on first use
+    fn kernels(self) -> &'static (Vec<i32>, Vec<i32>) {
+        static KERNELS: [OnceLock<(Vec<i32>, Vec<i32>)>; 7] = [const { OnceLock::new() }; 7];
+        KERNELS[self as usize].get_or_init(|| (self.x(), self.y()))
+    }
+
+    /// The column and row vectors whose outer product is `x`, for the
+    /// separable kernels. `y` is then the outer product of the row and
+    /// column.
+    fn factors(self) -> Option<&'static Factors> {
+        static FACTORS: [OnceLock<Option<Factors>>; 7] = [const { OnceLock::new() }; 7];
+        let factors = FACTORS[self as usize].get_or_init(|| match self {
+            Self::X5 => Some((vec![2, 1, 0, -1, -2], vec![1, 1, 2, 1, 1])),
+            Self::X3 | Self::X7 => {
+                let size = self.size();
+                Some((derivative(size), binomial(size - 1)))
+            }
+            Self::Scharr => Some((vec![-1, 0, 1], vec![3, 10, 3])),
+            Self::Prewitt => Some((vec![-1, 0, 1], vec![1, 1, 1])),
+            Self::Roberts | Self::LoG => None,
+        });
+        factors.as_ref()
+    }
+
+    /// The transpose of `x` for the first derivatives, the other diagonal
+    /// for Roberts and nothing for the Laplacian
     fn y(self) -> Vec<i32> {
+        let size = self.size();
         match self {
-            Self::X5 => vec![
-                2, 1, 0, -1, -2, 2, 1, 0, -1, -2, 4, 2, 0, -2, -4, 2, 1, 0, -1, -2, 2, 1, 0, -1, -2,
-            ],
+            Self::Roberts => vec![0, 0, 0, 0, 0, 1, 0, -1, 0],
+            Self::LoG => vec![0; size * size],
+            _ => {
+                let x = self.x();
+                (0..size * size)
+                    .map(|idx| x[idx / size + (idx % size) * size])
+                    .collect()
+            }
+        }
+    }
+}
 
-            Self::X3 => vec![-1, 0, 1, -2, 0, 2, -1, 0, 1],
+/// Binomial coefficients of the given row of Pascal's triangle
+fn binomial(n: usize) -> Vec<i32> {
+    let mut row = vec![1];
+    for _ in 0..n {
+        row = (0..=row.len())
+            .map(|i| row.get(i).unwrap_or(&0) + if i > 0 { row[i - 1] } else { 0 })
+            .collect();
+    }
+    row
+}
+
+/// The derivative of a binomial, the vertical factor of a `size`x`size`
+/// Sobel kernel
+fn derivative(size: usize) -> Vec<i32> {
+    binomial(size - 3)
+        .iter()
+        .chain([0, 0].iter())
+        .zip([0, 0].iter().chain(binomial(size - 3).iter()))
+        .map(|(a, b)| b - a)
+        .collect()
+}
+
+/// A `size`x`size` Sobel kernel differentiating along y: the outer product
+/// of a derivative of a binomial along y and a binomial along x.
+fn sobel_kernel(size: usize) -> Vec<i32> {
+    outer(&derivative(size), &binomial(size - 1))
+}
+
+fn outer(col: &[i32], row: &[i32]) -> Vec<i32> {
+    col.iter()
+        .flat_map(|c| row.iter().map(move |r| c * r))
+        .collect()
+}
+
+/// The pixels the kernel sees past the image border
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Border {
+    /// Leave the taps outside the image out of the sum. The border shows
+    /// as an edge against black.
+    #[default]
+    Truncate,
+    /// Repeat the border pixel
+    Replicate,
+    /// Mirror the image, border pixel included
+    Reflect,
+    /// Continue with the opposite side of the image. Pixels on both sides
+    /// influence each other, so the energy is never updated locally.
+    Wrap,
+    /// Pad with black, which sums the same as `Truncate`
+    Zero,
+}
+
+impl Border {
+    /// The index `idx` maps to along a line of `len` pixels, `None` if it
+    /// is outside and contributes nothing
+    #[inline]
+    fn index(self, idx: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        if (0..len).contains(&idx) {
+            return Some(idx as usize);
+        }
+        match self {
+            Self::Truncate | Self::Zero => None,
+            Self::Replicate => Some(idx.clamp(0, len - 1) as usize),
+            Self::Reflect => {
+                let idx = idx.rem_euclid(2 * len);
+                Some(min(idx, 2 * len - 1 - idx) as usize)
+            }
+            Self::Wrap => Some(idx.rem_euclid(len) as usize),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Sobel {
     kernel: Kernel,
+    border: Border,
 }
 
 impl Sobel {
     pub fn new() -> Self {
-        Self { kernel: Kernel::X3 }
+        Self {
+            kernel: Kernel::X3,
+            border: Border::Truncate,
+        }
     }
 
     pub fn kernel(mut self, kernel: Kernel) -> Self {
@@ -50,34 +210,253 @@ impl Sobel {
         self
     }
 
+    pub fn border(mut self, border: Border) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Half the kernel size, the distance up to which a pixel influences
+    /// the energy of its neighbours
+    pub fn kernel_radius(&self) -> usize {
+        self.kernel.size() / 2
+    }
+
     pub fn apply(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
-        let kernel_x = self.kernel.x();
-        let kernel_y = self.kernel.y();
         let mut buf = vec![0.0; image.len()];
+        self.update(image, width, height, &mut buf, 0..width, 0..height);
+        buf
+    }
+
+    /// Recompute the energy of the pixels in `cols` x `rows` only, leaving
+    /// the rest of `buf` untouched.
+    pub fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        let gradients = self.gradients(image, width, height, cols.clone(), rows.clone());
+        let pixels = rows.flat_map(|r| cols.clone().map(move |c| c + r * width));
+        for (idx, gradient) in pixels.zip(gradients) {
+            buf[idx] = magnitude(gradient);
+        }
+    }
+
+    /// The responses to the x and y kernels of the pixels in `cols` x
+    /// `rows`, row by row
+    fn gradients(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) -> Vec<(i32, i32)> {
+        match self.kernel.factors() {
+            Some(factors) => self.gradients_separable(factors, image, width, height, cols, rows),
+            None => self.gradients_2d(image, width, height, cols, rows),
+        }
+    }
+
+    /// Convolve with the whole kernel, for the kernels that are not
+    /// separable
+    fn gradients_2d(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) -> Vec<(i32, i32)> {
+        let mut res = vec![(0, 0); cols.len() * rows.len()];
+        fill_rows(&mut res, cols.len(), |line, i| {
+            self.gradients_row(image, width, height, cols.clone(), rows.start + i, line)
+        });
+        res
+    }
+
+    /// Convolve the rows the kernel reads with the row vectors, then the
+    /// columns of the result with the column vectors: 4k taps per pixel
+    /// instead of 2k². Both axes map the border on their own, so the sums
+    /// are the same as with the whole kernel.
+    fn gradients_separable(
+        &self,
+        (deriv, smooth): &Factors,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) -> Vec<(i32, i32)> {
         let ksize = self.kernel.size();
         let b = (ksize / 2) as isize;
-        for r in 0..height {
-            for c in 0..width {
-                let start_x = max(c as isize - b, 0) as usize;
-                let stop_x = min(c as isize + b, width as isize - 1) as usize;
-                let start_y = max(r as isize - b, 0) as usize;
-                let stop_y = min(r as isize + b, height as isize - 1) as usize;
-                let mut val_kx = 0;
-                let mut val_ky = 0;
-                for ix in start_x..=stop_x {
-                    for iy in start_y..=stop_y {
-                        let kx = ix + b as usize - c;
-                        let ky = iy + b as usize - r;
-                        let val_i = image[ix + iy * width] as i32;
-                        val_kx += val_i * kernel_x[kx + ky * ksize];
-                        val_ky += val_i * kernel_y[kx + ky * ksize];
+        // The image rows the kernel reads, and the line of `passes` of each
+        let mut slots = vec![usize::MAX; height];
+        let mut needed = Vec::new();
+        for r in rows.clone() {
+            for ky in 0..ksize {
+                if let Some(iy) = self.border.index(r as isize + ky as isize - b, height) {
+                    if slots[iy] == usize::MAX {
+                        slots[iy] = needed.len();
+                        needed.push(iy);
                     }
                 }
-                let mag = (val_kx.pow(2) + val_ky.pow(2)) as f32;
-                buf[c + r * width] = mag.powf(0.5);
             }
         }
-        buf
+        // The rows convolved with the smoothing vector for the x gradient
+        // and with the derivative for the y gradient
+        let mut passes = vec![(0, 0); cols.len() * needed.len()];
+        fill_rows(&mut passes, cols.len(), |line, i| {
+            let src = &image[needed[i] * width..(needed[i] + 1) * width];
+            let interior = max(cols.start, b as usize)
+                ..max(min(cols.end, width.saturating_sub(b as usize)), b as usize);
+            for c in cols.clone().filter(|c| !interior.contains(c)) {
+                let mut acc = (0, 0);
+                for kx in 0..ksize {
+                    if let Some(ix) = self.border.index(c as isize + kx as isize - b, width) {
+                        acc.0 += smooth[kx] * src[ix] as i32;
+                        acc.1 += deriv[kx] * src[ix] as i32;
+                    }
+                }
+                line[c - cols.start] = acc;
+            }
+            if interior.is_empty() {
+                return;
+            }
+            let out = &mut line[interior.start - cols.start..interior.end - cols.start];
+            for kx in 0..ksize {
+                let src = &src[interior.start + kx - b as usize..interior.end + kx - b as usize];
+                for (acc, &val) in out.iter_mut().zip(src) {
+                    acc.0 += smooth[kx] * val as i32;
+                    acc.1 += deriv[kx] * val as i32;
+                }
+            }
+        });
+        let mut res = vec![(0, 0); cols.len() * rows.len()];
+        fill_rows(&mut res, cols.len(), |line, i| {
+            let r = rows.start + i;
+            for ky in 0..ksize {
+                let Some(iy) = self.border.index(r as isize + ky as isize - b, height) else {
+                    continue;
+                };
+                let pass = &passes[slots[iy] * cols.len()..(slots[iy] + 1) * cols.len()];
+                for (acc, &(val_x, val_y)) in line.iter_mut().zip(pass) {
+                    acc.0 += deriv[ky] * val_x;
+                    acc.1 += smooth[ky] * val_y;
+                }
+            }
+        });
+        res
+    }
+
+    /// Fill `line` with the gradients of the pixels in `cols` on row `r`.
+    /// Where the whole kernel fits in the image, the taps are summed over
+    /// the row at once without checking the border, which vectorizes.
+    fn gradients_row(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        cols: Range<usize>,
+        r: usize,
+        line: &mut [(i32, i32)],
+    ) {
+        let (kernel_x, kernel_y) = self.kernel.kernels();
+        let ksize = self.kernel.size();
+        let b = ksize / 2;
+        let interior = match r >= b && r + b < height {
+            true => max(cols.start, b)..max(min(cols.end, width.saturating_sub(b)), b),
+            false => cols.start..cols.start,
+        };
+        for c in cols.clone().filter(|c| !interior.contains(c)) {
+            line[c - cols.start] = self.gradient(image, width, height, c, r);
+        }
+        if interior.is_empty() {
+            return;
+        }
+        let out = &mut line[interior.start - cols.start..interior.end - cols.start];
+        for ky in 0..ksize {
+            let src = &image[(r + ky - b) * width..];
+            for kx in 0..ksize {
+                let (wx, wy) = (kernel_x[kx + ky * ksize], kernel_y[kx + ky * ksize]);
+                if wx == 0 && wy == 0 {
+                    continue;
+                }
+                let src = &src[interior.start + kx - b..interior.end + kx - b];
+                for (acc, &val) in out.iter_mut().zip(src) {
+                    acc.0 += wx * val as i32;
+                    acc.1 += wy * val as i32;
+                }
+            }
+        }
+    }
+
+    /// The gradient of a single pixel, the kernel possibly crossing the
+    /// border
+    fn gradient(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        c: usize,
+        r: usize,
+    ) -> (i32, i32) {
+        let (kernel_x, kernel_y) = self.kernel.kernels();
+        let ksize = self.kernel.size();
+        let b = (ksize / 2) as isize;
+        let mut val_kx = 0;
+        let mut val_ky = 0;
+        for ky in 0..ksize {
+            let Some(iy) = self.border.index(r as isize + ky as isize - b, height) else {
+                continue;
+            };
+            for kx in 0..ksize {
+                let Some(ix) = self.border.index(c as isize + kx as isize - b, width) else {
+                    continue;
+                };
+                let val_i = image[ix + iy * width] as i32;
+                val_kx += val_i * kernel_x[kx + ky * ksize];
+                val_ky += val_i * kernel_y[kx + ky * ksize];
+            }
+        }
+        (val_kx, val_ky)
+    }
+}
+
+impl Energy for Sobel {
+    fn energy(&self, image: &[u8], width: usize, height: usize) -> Vec<f32> {
+        self.apply(image, width, height)
+    }
+
+    fn orientation_invariant(&self) -> bool {
+        true
+    }
+
+    fn radius(&self) -> Option<usize> {
+        match self.border {
+            Border::Wrap => None,
+            _ => Some(self.kernel_radius()),
+        }
+    }
+
+    fn set_kernel(&mut self, kernel: Kernel) -> bool {
+        self.kernel = kernel;
+        true
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        Sobel::update(self, image, width, height, buf, cols, rows)
     }
 }
 
@@ -87,6 +466,140 @@ impl Default for Sobel {
     }
 }
 
+/// Call `fill` with every line of `len` items of `buf` and its index,
+/// across threads for large buffers under the `parallel` feature
+fn fill_rows<T: Send>(buf: &mut [T], len: usize, fill: impl Fn(&mut [T], usize) + Send + Sync) {
+    if buf.is_empty() {
+        return;
+    }
+    #[cfg(feature = "parallel")]
+    if buf.len() >= PARALLEL_MIN_LEN && rayon::current_num_threads() > 1 {
+        use rayon::prelude::*;
+
+        buf.par_chunks_mut(len)
+            .enumerate()
+            .for_each(|(i, line)| fill(line, i));
+        return;
+    }
+    for (i, line) in buf.chunks_mut(len).enumerate() {
+        fill(line, i);
+    }
+}
+
+#[inline]
+fn magnitude((val_kx, val_ky): (i32, i32)) -> f32 {
+    let mag = ((val_kx as i64).pow(2) + (val_ky as i64).pow(2)) as f32;
+    mag.powf(0.5)
+}
+
+/// The paper's e_HoG: the gradient magnitude divided by the largest bin of
+/// the histogram of oriented gradients of the cell around every pixel.
+/// Pixels standing out of their cell keep a high energy, while repeated
+/// texture edges are damped.
+#[derive(Debug, Clone, Copy)]
+pub struct Hog {
+    sobel: Sobel,
+    cell: usize,
+    bins: usize,
+}
+
+impl Hog {
+    pub fn new() -> Self {
+        Self {
+            sobel: Sobel::new(),
+            cell: 11,
+            bins: 8,
+        }
+    }
+
+    /// The kernel of the gradients, [`Kernel::X3`] by default
+    pub fn kernel(mut self, kernel: Kernel) -> Self {
+        self.sobel = self.sobel.kernel(kernel);
+        self
+    }
+
+    /// What the kernel of the gradients sees past the image border,
+    /// [`Border::Truncate`] by default. The cells are always cut at the
+    /// border.
+    pub fn border(mut self, border: Border) -> Self {
+        self.sobel = self.sobel.border(border);
+        self
+    }
+
+    /// The side of the cell around every pixel, even sizes are rounded up
+    pub fn cell(mut self, cell: usize) -> Self {
+        self.cell = cell;
+        self
+    }
+
+    /// The number of orientations over half a turn, at least one
+    pub fn bins(mut self, bins: usize) -> Self {
+        self.bins = max(bins, 1);
+        self
+    }
+
+    #[inline]
+    fn bin(&self, (val_kx, val_ky): (i32, i32)) -> usize {
+        let angle = (val_ky as f32).atan2(val_kx as f32).rem_euclid(PI);
+        min((angle / PI * self.bins as f32) as usize, self.bins - 1)
+    }
+}
+
+impl Default for Hog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Energy for Hog {
+    fn radius(&self) -> Option<usize> {
+        Some(self.sobel.radius()? + self.cell / 2)
+    }
+
+    fn set_kernel(&mut self, kernel: Kernel) -> bool {
+        self.sobel.set_kernel(kernel)
+    }
+
+    fn update(
+        &self,
+        image: &[u8],
+        width: usize,
+        height: usize,
+        buf: &mut [f32],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) {
+        // Gradients of every pixel in the cells of the region
+        let b = self.cell / 2;
+        let cells_x = cols.start.saturating_sub(b)..min(cols.end + b, width);
+        let cells_y = rows.start.saturating_sub(b)..min(rows.end + b, height);
+        let stride = cells_x.len();
+        let gradients =
+            self.sobel
+                .gradients(image, width, height, cells_x.clone(), cells_y.clone());
+        let (mags, bins): (Vec<f32>, Vec<usize>) = gradients
+            .into_iter()
+            .map(|gradient| (magnitude(gradient), self.bin(gradient)))
+            .unzip();
+        let mut hist = vec![0.0; self.bins];
+        for r in rows {
+            for c in cols.clone() {
+                hist.fill(0.0);
+                let x = c.saturating_sub(b) - cells_x.start..min(c + b + 1, width) - cells_x.start;
+                for y in r.saturating_sub(b) - cells_y.start..min(r + b + 1, height) - cells_y.start
+                {
+                    for idx in x.clone().map(|x| x + y * stride) {
+                        hist[bins[idx]] += mags[idx];
+                    }
+                }
+                let max_bin = hist.iter().copied().fold(0.0, f32::max);
+                let mag = mags[c - cells_x.start + (r - cells_y.start) * stride];
+                buf[c + r * width] = if max_bin > 0.0 { mag / max_bin } else { 0.0 };
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,19 +615,331 @@ mod tests {
     #[case(Kernel::X5, "broadway_tower.jpg")]
     #[case(Kernel::X3, "broadway_tower.jpg")]
     #[case(Kernel::X3, "broadway_tower_flilpped.png")]
+    #[case(Kernel::X7, "saturn.jpg")]
+    #[case(Kernel::X7, "valve.png")]
+    #[case(Kernel::Scharr, "saturn.jpg")]
+    #[case(Kernel::Scharr, "valve.png")]
+    #[case(Kernel::Prewitt, "saturn.jpg")]
+    #[case(Kernel::Prewitt, "valve.png")]
+    #[case(Kernel::Roberts, "saturn.jpg")]
+    #[case(Kernel::Roberts, "valve.png")]
+    #[case(Kernel::LoG, "saturn.jpg")]
+    #[case(Kernel::LoG, "valve.png")]
+    #[case(Kernel::LoG, "broadway_tower.jpg")]
 
     fn test_sobel(#[case] kernel_type: Kernel, #[case] img_name: &str) {
         let src_path = format!("./test_data/src/{img_name}");
         let img = ImageReader::open(src_path).unwrap().decode().unwrap();
         let img = img.grayscale().into_luma8();
         let sobel = Sobel::new().kernel(kernel_type);
-        let result = sobel.apply(&img.as_raw(), img.width() as usize, img.height() as usize);
+        let result = sobel.apply(img.as_raw(), img.width() as usize, img.height() as usize);
         let width = img.width();
         let height = img.height();
         let result = result.iter().map(|&x| x as u8).collect();
         let result = GrayImage::from_raw(width as u32, height as u32, result).unwrap();
-        let ksize = kernel_type.size();
-        let fname = format!("./test_data/outputs/{img_name}_edges_kernel{ksize}x{ksize}.png");
+        let name = kernel_type.name();
+        let fname = format!("./test_data/outputs/{img_name}_edges_{name}.png");
+        result.save(fname).unwrap();
+    }
+
+    #[test]
+    fn test_generated_kernels() {
+        assert_eq!(vec![1, 4, 6, 4, 1], binomial(4));
+        assert_eq!(Kernel::X3.x(), sobel_kernel(3));
+        assert_eq!(vec![-1, 0, 1, -2, 0, 2, -1, 0, 1], Kernel::X3.y());
+
+        let x = Kernel::X7.x();
+        assert_eq!(vec![-1, -6, -15, -20, -15, -6, -1], x[..7]);
+        assert_eq!(vec![0; 7], x[21..28]);
+        assert_eq!(vec![4, 24, 60, 80, 60, 24, 4], x[35..42]);
+        // Every row of a derivative kernel sums to zero once transposed
+        let y = Kernel::X7.y();
+        assert!(y.chunks(7).all(|row| row.iter().sum::<i32>() == 0));
+        assert!(Kernel::Roberts.factors().is_none());
+        assert!(Kernel::LoG.factors().is_none());
+    }
+
+    #[rstest]
+    fn test_flat_image_has_no_edges(
+        #[values(
+            Kernel::X3,
+            Kernel::X5,
+            Kernel::X7,
+            Kernel::Scharr,
+            Kernel::Prewitt,
+            Kernel::Roberts,
+            Kernel::LoG
+        )]
+        kernel_type: Kernel,
+    ) {
+        let (width, height) = (12, 9);
+        let sobel = Sobel::new().kernel(kernel_type);
+        let energy = sobel.apply(&vec![100; width * height], width, height);
+        // The truncated borders do see an edge
+        let b = sobel.kernel_radius();
+        for r in b..height - b {
+            for c in b..width - b {
+                assert_eq!(0.0, energy[c + r * width]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_roberts_diagonals() {
+        // A step along the anti-diagonal only shows in the first kernel
+        let image: Vec<u8> = (0..25)
+            .map(|i| if i % 5 + i / 5 > 4 { 10 } else { 0 })
+            .collect();
+        let energy = Sobel::new().kernel(Kernel::Roberts).apply(&image, 5, 5);
+        assert_eq!(10.0, energy[2 + 5 * 2]);
+        assert_eq!(0.0, energy[1 + 5]);
+        assert_eq!(0.0, energy[2 + 5 * 3]);
+    }
+
+    #[rstest]
+    fn test_single_pixel_lines(
+        #[values(Kernel::X3, Kernel::X5, Kernel::X7, Kernel::Scharr)] kernel_type: Kernel,
+        #[values(1, 2, 7)] len: usize,
+    ) {
+        let image: Vec<u8> = (0..len).map(|v| (v * 40) as u8).collect();
+        let sobel = Sobel::new().kernel(kernel_type);
+        // The kernels are transposes of each other, so a line has the same
+        // energy whether it is a row or a column
+        let row = sobel.apply(&image, len, 1);
+        let col = sobel.apply(&image, 1, len);
+        assert_eq!(len, row.len());
+        assert_eq!(row, col);
+        assert!(row.iter().all(|v| v.is_finite()));
+    }
+
+    #[rstest]
+    fn test_separable(
+        #[values(Kernel::X3, Kernel::X5, Kernel::X7, Kernel::Scharr, Kernel::Prewitt)]
+        kernel_type: Kernel,
+    ) {
+        let (deriv, smooth) = kernel_type.factors().unwrap();
+        assert_eq!(kernel_type.x(), outer(deriv, smooth));
+        assert_eq!(kernel_type.y(), outer(smooth, deriv));
+
+        let sobel = Sobel::new().kernel(kernel_type);
+        for entry in std::fs::read_dir("./test_data/src").unwrap() {
+            let img = ImageReader::open(entry.unwrap().path())
+                .unwrap()
+                .decode()
+                .unwrap()
+                .into_luma8();
+            let (width, height) = (img.width() as usize, img.height() as usize);
+            let separable = sobel.gradients(img.as_raw(), width, height, 0..width, 0..height);
+            let full = sobel.gradients_2d(img.as_raw(), width, height, 0..width, 0..height);
+            assert!(separable == full);
+        }
+    }
+
+    #[rstest]
+    fn test_interior_matches_per_pixel(
+        #[values(
+            Kernel::X3,
+            Kernel::X5,
+            Kernel::X7,
+            Kernel::Scharr,
+            Kernel::Prewitt,
+            Kernel::Roberts,
+            Kernel::LoG
+        )]
+        kernel_type: Kernel,
+        #[values(Border::Truncate, Border::Reflect, Border::Wrap)] border: Border,
+    ) {
+        let (width, height) = (23, 17);
+        let image: Vec<u8> = (0..width * height)
+            .map(|v| (v * 7919 % 256) as u8)
+            .collect();
+        let sobel = Sobel::new().kernel(kernel_type).border(border);
+        let expected: Vec<(i32, i32)> = (0..height)
+            .flat_map(|r| (0..width).map(move |c| (c, r)))
+            .map(|(c, r)| sobel.gradient(&image, width, height, c, r))
+            .collect();
+        assert_eq!(
+            expected,
+            sobel.gradients(&image, width, height, 0..width, 0..height)
+        );
+        // Regions starting and ending inside, or on the border
+        for (cols, rows) in [(4..19, 3..14), (0..5, 0..17), (20..23, 2..3)] {
+            let region = sobel.gradients(&image, width, height, cols.clone(), rows.clone());
+            let rows = rows.flat_map(|r| cols.clone().map(move |c| c + r * width));
+            assert!(rows.map(|idx| expected[idx]).eq(region));
+        }
+    }
+
+    #[rstest]
+    fn test_update_region(
+        #[values(
+            Kernel::X3,
+            Kernel::X5,
+            Kernel::X7,
+            Kernel::Scharr,
+            Kernel::Prewitt,
+            Kernel::Roberts,
+            Kernel::LoG
+        )]
+        kernel_type: Kernel,
+    ) {
+        let img = ImageReader::open("./test_data/src/valve.png")
+            .unwrap()
+            .decode()
+            .unwrap()
+            .grayscale()
+            .into_luma8();
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let sobel = Sobel::new().kernel(kernel_type);
+        let expected = sobel.apply(img.as_raw(), width, height);
+        let mut buf = expected.clone();
+        for r in 10..20 {
+            for c in 0..width {
+                buf[c + r * width] = -1.0;
+            }
+        }
+        sobel.update(img.as_raw(), width, height, &mut buf, 0..width, 10..20);
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn test_border_index() {
+        let index = |border: Border| -> Vec<Option<usize>> {
+            (-2..6).map(|idx| border.index(idx, 4)).collect()
+        };
+        let some = |idx: [usize; 8]| idx.map(Some).to_vec();
+        let truncated = [None, None, Some(0), Some(1), Some(2), Some(3), None, None];
+        assert_eq!(truncated.to_vec(), index(Border::Truncate));
+        assert_eq!(truncated.to_vec(), index(Border::Zero));
+        assert_eq!(some([0, 0, 0, 1, 2, 3, 3, 3]), index(Border::Replicate));
+        assert_eq!(some([1, 0, 0, 1, 2, 3, 3, 2]), index(Border::Reflect));
+        assert_eq!(some([2, 3, 0, 1, 2, 3, 0, 1]), index(Border::Wrap));
+        // Kernels reaching further than a whole line
+        assert_eq!(Some(0), Border::Reflect.index(-3, 1));
+        assert_eq!(Some(0), Border::Reflect.index(-4, 2));
+        assert_eq!(Some(0), Border::Wrap.index(7, 1));
+    }
+
+    #[rstest]
+    fn test_border_symmetry(
+        #[values(
+            Border::Truncate,
+            Border::Replicate,
+            Border::Reflect,
+            Border::Wrap,
+            Border::Zero
+        )]
+        border: Border,
+        #[values("broadway_tower.jpg", "broadway_tower_flilpped.png")] img_name: &str,
+    ) {
+        use image::imageops::{flip_horizontal, rotate270, rotate90};
+        use image::{ImageBuffer, Luma};
+
+        let src_path = format!("./test_data/src/{img_name}");
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = img.grayscale().into_luma8();
+        let sobel = Sobel::new().border(border);
+        let energy = |img: &GrayImage| {
+            let (width, height) = img.dimensions();
+            let energy = sobel.apply(img.as_raw(), width as usize, height as usize);
+            ImageBuffer::<Luma<f32>, _>::from_raw(width, height, energy).unwrap()
+        };
+        let expected = energy(&img);
+        // Mirrored and rotated images have mirrored and rotated energies,
+        // whatever the border
+        assert!(expected == flip_horizontal(&energy(&flip_horizontal(&img))));
+        assert!(expected == rotate270(&energy(&rotate90(&img))));
+
+        // Only the border modes that invent pixels leave it without edges
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let left = expected.rows().map(|mut row| row.next().unwrap()[0]);
+        let left = left.sum::<f32>() / height as f32;
+        let mean = expected.iter().sum::<f32>() / (width * height) as f32;
+        match border {
+            Border::Truncate | Border::Zero => assert!(left > 5.0 * mean),
+            Border::Replicate | Border::Reflect => assert!(left < 5.0 * mean),
+            Border::Wrap => {}
+        }
+    }
+
+    #[test]
+    fn test_zero_is_truncate() {
+        let image: Vec<u8> = (0..99).map(|v| (v * 37 % 256) as u8).collect();
+        for kernel in [Kernel::X3, Kernel::X7, Kernel::LoG] {
+            let truncated = Sobel::new().kernel(kernel).apply(&image, 11, 9);
+            let zero = Sobel::new().kernel(kernel).border(Border::Zero);
+            assert_eq!(truncated, zero.apply(&image, 11, 9));
+        }
+    }
+
+    #[rstest]
+    #[case(Kernel::X3, 11, 8, "saturn.jpg")]
+    #[case(Kernel::X3, 11, 8, "valve.png")]
+    #[case(Kernel::X5, 7, 9, "broadway_tower.jpg")]
+    fn test_hog(
+        #[case] kernel_type: Kernel,
+        #[case] cell: usize,
+        #[case] bins: usize,
+        #[case] img_name: &str,
+    ) {
+        let src_path = format!("./test_data/src/{img_name}");
+        let img = ImageReader::open(src_path).unwrap().decode().unwrap();
+        let img = img.grayscale().into_luma8();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let hog = Hog::new().kernel(kernel_type).cell(cell).bins(bins);
+        let result = hog.energy(img.as_raw(), width, height);
+        // A pixel adds its own magnitude to one of the bins
+        assert!(result.iter().all(|&v| (0.0..=1.0).contains(&v)));
+
+        let mut buf = result.clone();
+        buf[width * 10..width * 20].fill(-1.0);
+        hog.update(img.as_raw(), width, height, &mut buf, 0..width, 10..20);
+        assert_eq!(result, buf);
+
+        let result = result.iter().map(|&x| (x * 255.0) as u8).collect();
+        let result = GrayImage::from_raw(width as u32, height as u32, result).unwrap();
+        let name = kernel_type.name();
+        let fname = format!("./test_data/outputs/{img_name}_hog_{name}_{cell}_{bins}.png");
         result.save(fname).unwrap();
     }
+
+    #[test]
+    fn test_hog_damps_texture() {
+        // Stripes two pixels wide, and a lone step on the last rows
+        let (width, height) = (16, 16);
+        let image: Vec<u8> = (0..width * height)
+            .map(|idx| match (idx % width, idx / width) {
+                (c, r) if r >= 12 => (c / 8 * 60) as u8,
+                (c, _) => (c / 2 % 2 * 60) as u8,
+            })
+            .collect();
+        let sobel = Sobel::new().apply(&image, width, height);
+        let hog = Hog::new().cell(5).energy(&image, width, height);
+        // Same gradient magnitude on the stripes and on the step
+        let (stripe, step) = (6 + 4 * width, 8 + 14 * width);
+        assert_eq!(sobel[stripe], sobel[step]);
+        assert!(hog[stripe] * 3.0 < hog[step]);
+        assert_eq!(0.0, hog[2 + 14 * width]);
+    }
+
+    #[rstest]
+    fn test_hog_border(
+        #[values(Border::Truncate, Border::Replicate, Border::Reflect, Border::Wrap)]
+        border: Border,
+    ) {
+        let (width, height) = (20, 15);
+        let image: Vec<u8> = (0..width * height).map(|v| (v * 37 % 256) as u8).collect();
+        let hog = Hog::new().cell(5).border(border);
+        let result = hog.energy(&image, width, height);
+        assert_eq!(border == Border::Wrap, hog.radius().is_none());
+        // The gradients see past the border the same way as Sobel's
+        let plain = Hog::new().cell(5).energy(&image, width, height);
+        assert_eq!(border == Border::Truncate, plain[0] == result[0]);
+
+        let mut buf = result.clone();
+        buf[width * 4..width * 9].fill(-1.0);
+        hog.update(&image, width, height, &mut buf, 0..width, 4..9);
+        assert_eq!(result, buf);
+    }
 }